@@ -1,74 +1,973 @@
+use actix_web::http::{header::{HeaderName, HeaderValue}, Method};
 use serde::Deserialize;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::str::FromStr;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    #[serde(default)]
     pub server: ServerConfig,
+    #[serde(default)]
     pub storage: StorageConfig,
+    #[serde(default)]
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub shortener: ShortenerConfig,
+    #[serde(default)]
+    pub security: SecurityConfig,
+    #[serde(default)]
+    pub cleanup: CleanupConfig,
+    #[serde(default)]
+    pub headers: HeadersConfig,
+    #[serde(default)]
+    pub analytics: AnalyticsConfig,
+    #[serde(default)]
+    pub webhook: WebhookConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct ServerConfig {
     pub host: IpAddr,
     pub port: u16,
+    /// Unix domain socket to listen on instead of `host` and `port`.
+    pub unix_socket: Option<String>,
+    /// Whether `host` or `port` was given in the config file or the environment, even with
+    /// the default value, so a socket path can't silently override an explicit address.
+    #[serde(skip)]
+    pub tcp_address_set: bool,
+    /// Scheme used when building short URLs from the bind address; always `https` with TLS.
+    pub scheme: String,
+    /// PEM certificate chain and private key; when both are set the server speaks HTTPS.
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// Externally visible base URL (e.g. `https://sho.rt`) used instead of the bind address.
+    pub public_base_url: Option<String>,
+    /// Extra hosts (optionally with a port) that links can be published under instead.
+    pub vanity_domains: Vec<String>,
     pub workers: usize,
+    pub shutdown_timeout_secs: u64,
+    /// Idle time before a keep-alive connection is closed; `0` disables keep-alive.
+    pub keep_alive_secs: u64,
+    /// Pending connections the listen socket queues before refusing new ones.
+    pub backlog: u32,
+    /// Gzip/Brotli-compress responses for clients that accept it; off when a proxy does it.
+    pub compression: bool,
+    /// Largest request body accepted before deserialization; imports have their own limit.
+    pub max_body_bytes: usize,
+    /// Most items accepted by one batch shorten, batch expand or import request.
+    pub max_batch_size: usize,
+    /// Body of `/robots.txt`.
+    pub robots_txt: String,
+    /// Icon file served at `/favicon.ico`; without one the path answers `204`.
+    pub favicon_path: Option<String>,
+    /// Directory whose files are served under `/.well-known/`, e.g. for ACME challenges.
+    pub well_known_dir: Option<String>,
+    /// Reverse proxies whose `Forwarded`/`X-Forwarded-For` headers name the client; from any
+    /// other peer those headers are ignored, since clients can set them to anything.
+    pub trusted_proxies: Vec<IpAddr>,
+    pub allowed_origins: Vec<String>,
+    pub allowed_methods: Vec<String>,
+    pub allowed_headers: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Sled,
+    Memory,
+}
+
+impl FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "sled" => Ok(Self::Sled),
+            "memory" => Ok(Self::Memory),
+            other => Err(format!("STORAGE_BACKEND must be 'sled' or 'memory', got '{}'", other)),
+        }
+    }
+}
+
+/// sled's space/speed trade-off; `LowSpace` compacts more eagerly at some throughput cost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    HighThroughput,
+    LowSpace,
+}
+
+impl FromStr for StorageMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "high_throughput" => Ok(Self::HighThroughput),
+            "low_space" => Ok(Self::LowSpace),
+            other => Err(format!("STORAGE_MODE must be 'high_throughput' or 'low_space', got '{}'", other)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct StorageConfig {
+    pub backend: StorageBackend,
     pub path: String,
+    pub mode: StorageMode,
     pub cache_size_mb: usize,
     pub flush_interval_ms: u64,
+    /// Flush after every single-link write; when off, sled's periodic flush covers them.
+    pub flush_on_write: bool,
+    /// Retries for mapping reads and writes that fail with an IO error; `0` disables retrying.
+    pub max_retries: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "pretty" => Ok(Self::Pretty),
+            "json" => Ok(Self::Json),
+            other => Err(format!("LOG_FORMAT must be 'pretty' or 'json', got '{}'", other)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct LoggingConfig {
     pub level: String,
+    /// `json` emits one JSON object per event for log aggregators.
+    pub format: LogFormat,
+}
+
+/// How codes are generated: random nanoids, or a persisted counter written in the code
+/// alphabet, which gives ordered but guessable codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CodeStrategy {
+    Nanoid,
+    Sequential,
+}
+
+impl FromStr for CodeStrategy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "nanoid" => Ok(Self::Nanoid),
+            "sequential" => Ok(Self::Sequential),
+            other => Err(format!("CODE_STRATEGY must be 'nanoid' or 'sequential', got '{}'", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ShortenerConfig {
+    pub code_strategy: CodeStrategy,
+    /// First counter value a fresh database hands out with the sequential strategy; higher
+    /// values skip the shortest codes.
+    pub code_sequence_start: u64,
+    /// Length of nanoid codes; sequential codes grow with the counter instead.
+    pub code_length: usize,
+    /// Characters generated codes are drawn from.
+    pub code_alphabet: String,
+    /// Inclusive length bounds for custom aliases.
+    pub alias_min_length: usize,
+    pub alias_max_length: usize,
+    pub redirect_status: u16,
+    /// How long browsers and CDNs may cache a 301; `0` asks them to revalidate every time.
+    pub redirect_cache_secs: u64,
+    pub reserved_codes: Vec<String>,
+    pub allowed_domains: Vec<String>,
+    pub blocked_domains: Vec<String>,
+    pub allowed_schemes: Vec<String>,
+    pub strip_fragments: bool,
+    pub dedup_enabled: bool,
+    pub max_url_length: usize,
+    /// Store custom aliases in lowercase and fall back to lowercase on lookup.
+    pub case_insensitive_aliases: bool,
+    /// Accept aliases made only of digits; off keeps them free for numeric routes.
+    pub allow_numeric_aliases: bool,
+    /// Upper bound on the HEAD probe made for `verify_reachable` requests.
+    pub reachability_timeout_ms: u64,
+    /// How long a deleted link can be restored; `0` deletes immediately.
+    pub delete_undo_window_secs: u64,
+    /// How long an `Idempotency-Key` replays its first response; `0` ignores the header.
+    pub idempotency_window_secs: u64,
+    /// Resolve destination hosts and reject those on private, loopback or link-local addresses.
+    pub block_private_hosts: bool,
+    /// Append an HMAC checksum to generated codes so forged ones are rejected without a lookup.
+    pub signed_codes: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum EncryptionAlgorithm {
+    #[default]
+    #[serde(rename = "aes-256-gcm")]
+    Aes256Gcm,
+    #[serde(rename = "chacha20-poly1305")]
+    ChaCha20Poly1305,
+}
+
+impl EncryptionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Aes256Gcm => "aes-256-gcm",
+            Self::ChaCha20Poly1305 => "chacha20-poly1305",
+        }
+    }
+}
+
+impl FromStr for EncryptionAlgorithm {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "aes-256-gcm" => Ok(Self::Aes256Gcm),
+            "chacha20-poly1305" => Ok(Self::ChaCha20Poly1305),
+            other => Err(format!(
+                "ENCRYPTION_ALGORITHM must be 'aes-256-gcm' or 'chacha20-poly1305', got '{}'", other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SecurityConfig {
+    pub api_keys: Vec<String>,
+    /// Encrypt destinations at rest; when off, new links store them in plaintext.
+    pub encryption_enabled: bool,
+    pub encryption_algorithm: EncryptionAlgorithm,
+    /// Read-only key file (e.g. a mounted secret) used instead of the `keys/` directory.
+    pub encryption_key_file: Option<String>,
+    /// HMAC key for `signed_codes`; changing it invalidates every signed code.
+    pub code_signing_secret: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CleanupConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+}
+
+/// Security headers added to every response; each can be turned off for API-only deployments.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HeadersConfig {
+    pub nosniff: bool,
+    pub frame_deny: bool,
+    pub referrer_policy: Option<String>,
+    pub content_security_policy: Option<String>,
 }
 
+/// Click analytics. Client IPs are only captured with `privacy_mode` off, and then only as a
+/// keyed hash so unique visitors can be counted without storing addresses.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AnalyticsConfig {
+    /// Count clicks by referrer host and user-agent category.
+    pub enabled: bool,
+    pub privacy_mode: bool,
+    /// Per-deployment HMAC key for hashing client IPs; required when `privacy_mode` is off.
+    pub ip_salt: Option<String>,
+    /// Days of per-day click buckets kept before the cleanup sweep drops them.
+    pub daily_retention_days: u32,
+}
+
+/// Outgoing notifications. Delivery is fire-and-forget: failures are logged and retried,
+/// but never fail the request that triggered them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WebhookConfig {
+    /// Receives a POST with the new link's details whenever a short URL is created.
+    pub creation_url: Option<String>,
+    pub timeout_ms: u64,
+    /// Extra attempts after the first failed delivery.
+    pub retries: u32,
+}
+
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+const DEFAULT_HOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_SCHEME: &str = "http";
 const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 30;
+const DEFAULT_KEEP_ALIVE: u64 = 5;
+const MAX_KEEP_ALIVE: u64 = 3600;
+const DEFAULT_BACKLOG: u32 = 2048;
+const DEFAULT_MAX_BODY_BYTES: usize = 16 * 1024;
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+const DEFAULT_ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
+const DEFAULT_ALLOWED_ORIGINS: &[&str] = &["http://localhost:3000"];
+const DEFAULT_ALLOWED_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE"];
+const DEFAULT_ALLOWED_HEADERS: &[&str] = &["Content-Type", "X-API-Key", "X-Manage-Token", "Idempotency-Key"];
+const DEFAULT_STORAGE_PATH: &str = "url_db";
 const DEFAULT_CACHE_SIZE: usize = 64;
 const DEFAULT_FLUSH_INTERVAL: u64 = 1000;
+const DEFAULT_STORAGE_RETRIES: u32 = 3;
+const MAX_STORAGE_RETRIES: u32 = 10;
 const DEFAULT_LOG_LEVEL: &str = "info";
+const LOG_LEVELS: &[&str] = &["trace", "debug", "info", "warn", "error", "off"];
+const DEFAULT_CODE_LENGTH: usize = 6;
+const MIN_CODE_LENGTH: usize = 4;
+const MAX_CODE_LENGTH: usize = 32;
+const DEFAULT_ALIAS_MIN_LENGTH: usize = 3;
+const DEFAULT_ALIAS_MAX_LENGTH: usize = 32;
+const DEFAULT_CODE_ALPHABET: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+/// 62^3, the first four-character code with the default alphabet.
+const DEFAULT_CODE_SEQUENCE_START: u64 = 238_328;
+const DEFAULT_REDIRECT_STATUS: u16 = 302;
+const DEFAULT_MAX_URL_LENGTH: usize = 2048;
+const DEFAULT_REACHABILITY_TIMEOUT: u64 = 3000;
+const DEFAULT_DELETE_UNDO_WINDOW: u64 = 24 * 60 * 60;
+const DEFAULT_IDEMPOTENCY_WINDOW: u64 = 60 * 60;
+const DEFAULT_ALLOWED_SCHEMES: &[&str] = &["http", "https"];
+const DEFAULT_CLEANUP_INTERVAL: u64 = 60;
+const DEFAULT_DAILY_RETENTION_DAYS: u32 = 90;
+const DEFAULT_WEBHOOK_TIMEOUT: u64 = 5000;
+const DEFAULT_WEBHOOK_RETRIES: u32 = 3;
+const MAX_WEBHOOK_RETRIES: u32 = 10;
+const DEFAULT_REFERRER_POLICY: &str = "no-referrer";
+const DEFAULT_RESERVED_CODES: &[&str] = &["api", "health", "metrics", "livez", "readyz", "favicon.ico", "robots.txt"];
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: DEFAULT_HOST,
+            port: DEFAULT_PORT,
+            tcp_address_set: false,
+            unix_socket: None,
+            workers: DEFAULT_WORKERS,
+            scheme: DEFAULT_SCHEME.to_string(),
+            tls_cert_path: None,
+            tls_key_path: None,
+            public_base_url: None,
+            vanity_domains: Vec::new(),
+            shutdown_timeout_secs: DEFAULT_SHUTDOWN_TIMEOUT,
+            keep_alive_secs: DEFAULT_KEEP_ALIVE,
+            backlog: DEFAULT_BACKLOG,
+            compression: true,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            robots_txt: DEFAULT_ROBOTS_TXT.to_string(),
+            favicon_path: None,
+            well_known_dir: None,
+            trusted_proxies: Vec::new(),
+            allowed_origins: to_strings(DEFAULT_ALLOWED_ORIGINS),
+            allowed_methods: to_strings(DEFAULT_ALLOWED_METHODS),
+            allowed_headers: to_strings(DEFAULT_ALLOWED_HEADERS),
+        }
+    }
+}
+
+impl Default for SecurityConfig {
+    fn default() -> Self {
+        Self {
+            api_keys: Vec::new(),
+            encryption_enabled: true,
+            encryption_algorithm: EncryptionAlgorithm::default(),
+            encryption_key_file: None,
+            code_signing_secret: None,
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            backend: StorageBackend::Sled,
+            path: DEFAULT_STORAGE_PATH.to_string(),
+            mode: StorageMode::HighThroughput,
+            cache_size_mb: DEFAULT_CACHE_SIZE,
+            flush_interval_ms: DEFAULT_FLUSH_INTERVAL,
+            flush_on_write: true,
+            max_retries: DEFAULT_STORAGE_RETRIES,
+        }
+    }
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: DEFAULT_LOG_LEVEL.to_string(),
+            format: LogFormat::Pretty,
+        }
+    }
+}
+
+impl Default for ShortenerConfig {
+    fn default() -> Self {
+        Self {
+            code_strategy: CodeStrategy::Nanoid,
+            code_sequence_start: DEFAULT_CODE_SEQUENCE_START,
+            code_length: DEFAULT_CODE_LENGTH,
+            alias_min_length: DEFAULT_ALIAS_MIN_LENGTH,
+            alias_max_length: DEFAULT_ALIAS_MAX_LENGTH,
+            code_alphabet: DEFAULT_CODE_ALPHABET.to_string(),
+            redirect_status: DEFAULT_REDIRECT_STATUS,
+            redirect_cache_secs: 0,
+            reserved_codes: to_strings(DEFAULT_RESERVED_CODES),
+            allowed_domains: Vec::new(),
+            blocked_domains: Vec::new(),
+            allowed_schemes: to_strings(DEFAULT_ALLOWED_SCHEMES),
+            strip_fragments: true,
+            dedup_enabled: true,
+            max_url_length: DEFAULT_MAX_URL_LENGTH,
+            case_insensitive_aliases: false,
+            allow_numeric_aliases: true,
+            reachability_timeout_ms: DEFAULT_REACHABILITY_TIMEOUT,
+            delete_undo_window_secs: DEFAULT_DELETE_UNDO_WINDOW,
+            idempotency_window_secs: DEFAULT_IDEMPOTENCY_WINDOW,
+            block_private_hosts: false,
+            signed_codes: false,
+        }
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+/// Splits a comma-separated env var, keeping `current` when it is unset.
+fn env_list(key: &str, current: Vec<String>) -> Vec<String> {
+    match env::var(key) {
+        Ok(v) => v
+            .split(',')
+            .map(str::trim)
+            .filter(|item| !item.is_empty())
+            .map(String::from)
+            .collect(),
+        Err(_) => current,
+    }
+}
+
+/// Accepts a bare level (`info`) or a per-target one (`actix_web=warn`); empty entries are ignored.
+fn is_valid_log_directive(directive: &str) -> bool {
+    if directive.is_empty() {
+        return true;
+    }
+    let level = directive.rsplit_once('=').map_or(directive, |(_, level)| level);
+    LOG_LEVELS.contains(&level.trim().to_lowercase().as_str())
+}
+
+/// Parses an env var, keeping `current` when it is unset. A malformed value is an error rather
+/// than a silent fallback, so a typo in e.g. `BLOCK_PRIVATE_HOSTS` cannot quietly disable it.
+fn env_or<T: FromStr>(key: &str, current: T) -> Result<T, String> {
+    match env::var(key) {
+        Ok(v) => v.parse().map_err(|_| format!("{} has an invalid value, got {:?}", key, v)),
+        Err(_) => Ok(current),
+    }
+}
+
+impl Default for CleanupConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval_secs: DEFAULT_CLEANUP_INTERVAL,
+        }
+    }
+}
+
+impl Default for HeadersConfig {
+    fn default() -> Self {
+        Self {
+            nosniff: true,
+            frame_deny: true,
+            referrer_policy: Some(DEFAULT_REFERRER_POLICY.to_string()),
+            content_security_policy: None,
+        }
+    }
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            privacy_mode: true,
+            ip_salt: None,
+            daily_retention_days: DEFAULT_DAILY_RETENTION_DAYS,
+        }
+    }
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            creation_url: None,
+            timeout_ms: DEFAULT_WEBHOOK_TIMEOUT,
+            retries: DEFAULT_WEBHOOK_RETRIES,
+        }
+    }
+}
 
 impl Config {
+    /// Loads configuration from environment variables only.
     pub fn new() -> Result<Self, Box<dyn std::error::Error>> {
-        let host = env::var("SERVER_HOST").unwrap_or_else(|_| "127.0.0.1".to_string());
-        let port = env::var("SERVER_PORT").unwrap_or_else(|_| "8080".to_string());
-        let workers = env::var("SERVER_WORKERS")
-            .map(|v| v.parse().unwrap_or(DEFAULT_WORKERS))
-            .unwrap_or(DEFAULT_WORKERS);
-
-        Ok(Self {
-            server: ServerConfig {
-                host: IpAddr::from_str(&host)?,
-                port: port.parse()?,
-                workers,
-            },
-            storage: StorageConfig {
-                path: env::var("STORAGE_PATH").unwrap_or_else(|_| "url_db".to_string()),
-                cache_size_mb: env::var("STORAGE_CACHE_SIZE_MB")
-                    .map(|v| v.parse().unwrap_or(DEFAULT_CACHE_SIZE))
-                    .unwrap_or(DEFAULT_CACHE_SIZE),
-                flush_interval_ms: env::var("STORAGE_FLUSH_INTERVAL_MS")
-                    .map(|v| v.parse().unwrap_or(DEFAULT_FLUSH_INTERVAL))
-                    .unwrap_or(DEFAULT_FLUSH_INTERVAL),
-            },
-            logging: LoggingConfig {
-                level: env::var("RUST_LOG").unwrap_or_else(|_| DEFAULT_LOG_LEVEL.to_string()),
-            },
+        Self::with_env_overrides(Self {
+            server: ServerConfig::default(),
+            storage: StorageConfig::default(),
+            logging: LoggingConfig::default(),
+            shortener: ShortenerConfig::default(),
+            security: SecurityConfig::default(),
+            cleanup: CleanupConfig::default(),
+            headers: HeadersConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            webhook: WebhookConfig::default(),
         })
     }
 
+    /// Loads configuration from a TOML file; environment variables take precedence over file values.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+        let mut file_config: Self = toml::from_str(&contents)
+            .map_err(|e| format!("Failed to parse config file {}: {}", path.display(), e))?;
+        let file_server = toml::from_str::<toml::Table>(&contents)?
+            .get("server")
+            .and_then(|server| server.as_table().cloned())
+            .unwrap_or_default();
+        file_config.server.tcp_address_set = file_server.contains_key("host") || file_server.contains_key("port");
+
+        Self::with_env_overrides(file_config)
+    }
+
+    /// Uses `CONFIG_FILE` (or `config.toml` when present), falling back to env-only configuration.
+    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
+        match env::var("CONFIG_FILE") {
+            Ok(path) => Self::from_file(path),
+            Err(_) if Path::new(DEFAULT_CONFIG_FILE).exists() => Self::from_file(DEFAULT_CONFIG_FILE),
+            Err(_) => Self::new(),
+        }
+    }
+
+    fn with_env_overrides(mut config: Self) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Ok(host) = env::var("SERVER_HOST") {
+            config.server.host = IpAddr::from_str(&host)?;
+            config.server.tcp_address_set = true;
+        }
+        if let Ok(port) = env::var("SERVER_PORT") {
+            config.server.port = port.parse()?;
+            config.server.tcp_address_set = true;
+        }
+        if let Ok(path) = env::var("SERVER_UNIX_SOCKET") {
+            config.server.unix_socket = Some(path);
+        }
+        config.server.unix_socket = config.server.unix_socket.filter(|path| !path.is_empty());
+        if let Ok(scheme) = env::var("SERVER_SCHEME") {
+            config.server.scheme = scheme.to_lowercase();
+        }
+        if let Ok(path) = env::var("TLS_CERT_PATH") {
+            config.server.tls_cert_path = Some(path);
+        }
+        config.server.tls_cert_path = config.server.tls_cert_path.filter(|path| !path.is_empty());
+        if let Ok(path) = env::var("TLS_KEY_PATH") {
+            config.server.tls_key_path = Some(path);
+        }
+        config.server.tls_key_path = config.server.tls_key_path.filter(|path| !path.is_empty());
+        if let Ok(public_base_url) = env::var("PUBLIC_BASE_URL") {
+            config.server.public_base_url = Some(public_base_url);
+        }
+        config.server.public_base_url = config.server.public_base_url
+            .map(|url| url.trim().trim_end_matches('/').to_string())
+            .filter(|url| !url.is_empty());
+        config.server.vanity_domains = env_list("VANITY_DOMAINS", config.server.vanity_domains)
+            .into_iter()
+            .map(|domain| domain.to_lowercase())
+            .collect();
+        config.server.workers = env_or("SERVER_WORKERS", config.server.workers)?;
+        config.server.shutdown_timeout_secs = env_or("SERVER_SHUTDOWN_TIMEOUT_SECS", config.server.shutdown_timeout_secs)?;
+        config.server.keep_alive_secs = env_or("SERVER_KEEP_ALIVE_SECS", config.server.keep_alive_secs)?;
+        config.server.backlog = env_or("SERVER_BACKLOG", config.server.backlog)?;
+        config.server.compression = env_or("SERVER_COMPRESSION", config.server.compression)?;
+        config.server.max_body_bytes = env_or("MAX_BODY_BYTES", config.server.max_body_bytes)?;
+        config.server.max_batch_size = env_or("MAX_BATCH_SIZE", config.server.max_batch_size)?;
+        // Literal `\n` sequences allow a multi-line policy in a single-line variable
+        if let Ok(robots_txt) = env::var("ROBOTS_TXT") {
+            config.server.robots_txt = robots_txt.replace("\\n", "\n");
+        }
+        if let Ok(path) = env::var("FAVICON_PATH") {
+            config.server.favicon_path = Some(path);
+        }
+        config.server.favicon_path = config.server.favicon_path.filter(|path| !path.is_empty());
+        if let Ok(dir) = env::var("WELL_KNOWN_DIR") {
+            config.server.well_known_dir = Some(dir);
+        }
+        config.server.well_known_dir = config.server.well_known_dir.filter(|dir| !dir.is_empty());
+        if env::var("TRUSTED_PROXIES").is_ok() {
+            config.server.trusted_proxies = env_list("TRUSTED_PROXIES", Vec::new())
+                .iter()
+                .map(|proxy| {
+                    proxy.parse()
+                        .map_err(|_| format!("TRUSTED_PROXIES must contain IP addresses, got '{}'", proxy))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        config.server.allowed_origins = env_list("CORS_ALLOWED_ORIGINS", config.server.allowed_origins);
+        config.server.allowed_methods = env_list("CORS_ALLOWED_METHODS", config.server.allowed_methods);
+        config.server.allowed_headers = env_list("CORS_ALLOWED_HEADERS", config.server.allowed_headers);
+
+        if let Ok(backend) = env::var("STORAGE_BACKEND") {
+            config.storage.backend = backend.parse()?;
+        }
+        if let Ok(path) = env::var("STORAGE_PATH") {
+            config.storage.path = path;
+        }
+        if let Ok(mode) = env::var("STORAGE_MODE") {
+            config.storage.mode = mode.parse()?;
+        }
+        config.storage.cache_size_mb = env_or("STORAGE_CACHE_SIZE_MB", config.storage.cache_size_mb)?;
+        config.storage.flush_interval_ms = env_or("STORAGE_FLUSH_INTERVAL_MS", config.storage.flush_interval_ms)?;
+        config.storage.flush_on_write = env_or("STORAGE_FLUSH_ON_WRITE", config.storage.flush_on_write)?;
+        config.storage.max_retries = env_or("STORAGE_MAX_RETRIES", config.storage.max_retries)?;
+
+        if let Ok(level) = env::var("RUST_LOG") {
+            config.logging.level = level;
+        }
+        if let Ok(format) = env::var("LOG_FORMAT") {
+            config.logging.format = format.parse()?;
+        }
+
+        if let Ok(strategy) = env::var("CODE_STRATEGY") {
+            config.shortener.code_strategy = strategy.parse()?;
+        }
+        config.shortener.code_sequence_start = env_or("CODE_SEQUENCE_START", config.shortener.code_sequence_start)?;
+        config.shortener.code_length = env_or("CODE_LENGTH", config.shortener.code_length)?;
+        config.shortener.alias_min_length = env_or("ALIAS_MIN_LEN", config.shortener.alias_min_length)?;
+        config.shortener.alias_max_length = env_or("ALIAS_MAX_LEN", config.shortener.alias_max_length)?;
+        if let Ok(alphabet) = env::var("CODE_ALPHABET") {
+            config.shortener.code_alphabet = alphabet;
+        }
+        if let Ok(v) = env::var("REDIRECT_STATUS") {
+            config.shortener.redirect_status = v.trim()
+                .parse()
+                .map_err(|_| format!("REDIRECT_STATUS must be 301 or 302, got '{}'", v))?;
+        }
+        config.shortener.redirect_cache_secs = env_or("REDIRECT_CACHE_SECS", config.shortener.redirect_cache_secs)?;
+
+        config.shortener.reserved_codes = env_list("RESERVED_CODES", config.shortener.reserved_codes);
+        config.shortener.allowed_domains = env_list("ALLOWED_DOMAINS", config.shortener.allowed_domains);
+        config.shortener.blocked_domains = env_list("BLOCKED_DOMAINS", config.shortener.blocked_domains);
+        config.shortener.allowed_schemes = env_list("ALLOWED_SCHEMES", config.shortener.allowed_schemes);
+        config.shortener.strip_fragments = env_or("STRIP_URL_FRAGMENTS", config.shortener.strip_fragments)?;
+        config.shortener.dedup_enabled = env_or("DEDUP_ENABLED", config.shortener.dedup_enabled)?;
+        config.shortener.max_url_length = env_or("MAX_URL_LENGTH", config.shortener.max_url_length)?;
+        config.shortener.case_insensitive_aliases = env_or("CASE_INSENSITIVE_ALIASES", config.shortener.case_insensitive_aliases)?;
+        config.shortener.allow_numeric_aliases = env_or("ALLOW_NUMERIC_ALIASES", config.shortener.allow_numeric_aliases)?;
+        config.shortener.reachability_timeout_ms = env_or("REACHABILITY_TIMEOUT_MS", config.shortener.reachability_timeout_ms)?;
+        config.shortener.delete_undo_window_secs = env_or("DELETE_UNDO_WINDOW_SECS", config.shortener.delete_undo_window_secs)?;
+        config.shortener.idempotency_window_secs = env_or("IDEMPOTENCY_WINDOW_SECS", config.shortener.idempotency_window_secs)?;
+        config.shortener.block_private_hosts = env_or("BLOCK_PRIVATE_HOSTS", config.shortener.block_private_hosts)?;
+        config.shortener.signed_codes = env_or("SIGNED_CODES", config.shortener.signed_codes)?;
+
+        config.security.api_keys = env_list("API_KEYS", config.security.api_keys);
+        config.security.encryption_enabled = env_or("ENCRYPTION_ENABLED", config.security.encryption_enabled)?;
+        if let Ok(algorithm) = env::var("ENCRYPTION_ALGORITHM") {
+            config.security.encryption_algorithm = algorithm.parse()?;
+        }
+        if let Ok(path) = env::var("ENCRYPTION_KEY_FILE") {
+            config.security.encryption_key_file = Some(path);
+        }
+        if let Ok(secret) = env::var("CODE_SIGNING_SECRET") {
+            config.security.code_signing_secret = Some(secret);
+        }
+        config.security.code_signing_secret = config.security.code_signing_secret.filter(|secret| !secret.is_empty());
+
+        config.cleanup.enabled = env_or("CLEANUP_ENABLED", config.cleanup.enabled)?;
+        config.cleanup.interval_secs = env_or("CLEANUP_INTERVAL_SECS", config.cleanup.interval_secs)?;
+
+        config.headers.nosniff = env_or("HEADER_NOSNIFF", config.headers.nosniff)?;
+        config.headers.frame_deny = env_or("HEADER_FRAME_DENY", config.headers.frame_deny)?;
+        if let Ok(policy) = env::var("REFERRER_POLICY") {
+            config.headers.referrer_policy = Some(policy);
+        }
+        if let Ok(policy) = env::var("CONTENT_SECURITY_POLICY") {
+            config.headers.content_security_policy = Some(policy);
+        }
+        // An empty policy turns the header off
+        config.headers.referrer_policy = config.headers.referrer_policy
+            .map(|policy| policy.trim().to_string())
+            .filter(|policy| !policy.is_empty());
+        config.headers.content_security_policy = config.headers.content_security_policy
+            .map(|policy| policy.trim().to_string())
+            .filter(|policy| !policy.is_empty());
+
+        config.analytics.enabled = env_or("ANALYTICS_ENABLED", config.analytics.enabled)?;
+        config.analytics.privacy_mode = env_or("PRIVACY_MODE", config.analytics.privacy_mode)?;
+        if let Ok(salt) = env::var("ANALYTICS_IP_SALT") {
+            config.analytics.ip_salt = Some(salt);
+        }
+        config.analytics.ip_salt = config.analytics.ip_salt.filter(|salt| !salt.is_empty());
+        config.analytics.daily_retention_days = env_or("DAILY_CLICKS_RETENTION_DAYS", config.analytics.daily_retention_days)?;
+
+        if let Ok(url) = env::var("CREATION_WEBHOOK_URL") {
+            config.webhook.creation_url = Some(url);
+        }
+        config.webhook.creation_url = config.webhook.creation_url
+            .map(|url| url.trim().to_string())
+            .filter(|url| !url.is_empty());
+        config.webhook.timeout_ms = env_or("WEBHOOK_TIMEOUT_MS", config.webhook.timeout_ms)?;
+        config.webhook.retries = env_or("WEBHOOK_RETRIES", config.webhook.retries)?;
+
+        Ok(config)
+    }
+
+    /// Rejects values the server can't run with; called once at startup so bad config fails fast.
+    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.server.workers == 0 {
+            return Err("SERVER_WORKERS must be greater than 0".into());
+        }
+        if self.server.keep_alive_secs > MAX_KEEP_ALIVE {
+            return Err(format!(
+                "SERVER_KEEP_ALIVE_SECS must be at most {}, got {}",
+                MAX_KEEP_ALIVE, self.server.keep_alive_secs
+            ).into());
+        }
+        if self.server.backlog == 0 {
+            return Err("SERVER_BACKLOG must be greater than 0".into());
+        }
+        if cfg!(not(unix)) && self.server.unix_socket.is_some() {
+            return Err("SERVER_UNIX_SOCKET is only supported on Unix platforms".into());
+        }
+        if self.server.unix_socket.is_some() {
+            if self.server.tcp_address_set {
+                return Err("SERVER_HOST and SERVER_PORT can't be combined with SERVER_UNIX_SOCKET".into());
+            }
+            if self.server.public_base_url.is_none() {
+                return Err("PUBLIC_BASE_URL is required with SERVER_UNIX_SOCKET, since short links can't be derived from a socket path".into());
+            }
+        }
+        if self.server.tls_cert_path.is_some() != self.server.tls_key_path.is_some() {
+            return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".into());
+        }
+        if self.tls_enabled() && self.server.unix_socket.is_some() {
+            return Err("TLS_CERT_PATH and TLS_KEY_PATH can't be combined with SERVER_UNIX_SOCKET".into());
+        }
+        if self.server.max_body_bytes == 0 {
+            return Err("MAX_BODY_BYTES must be greater than 0".into());
+        }
+        if self.server.max_batch_size == 0 {
+            return Err("MAX_BATCH_SIZE must be greater than 0".into());
+        }
+        if !matches!(self.server.scheme.as_str(), "http" | "https") {
+            return Err(format!("SERVER_SCHEME must be 'http' or 'https', got '{}'", self.server.scheme).into());
+        }
+        if let Some(public_base_url) = &self.server.public_base_url {
+            let parsed = url::Url::parse(public_base_url)
+                .map_err(|_| format!("PUBLIC_BASE_URL is not a valid URL: '{}'", public_base_url))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(format!("PUBLIC_BASE_URL must use http or https, got '{}'", public_base_url).into());
+            }
+        }
+        for domain in &self.server.vanity_domains {
+            let valid = url::Url::parse(&format!("http://{}", domain))
+                .is_ok_and(|parsed| parsed.path() == "/" && parsed.query().is_none() && parsed.username().is_empty());
+            if !valid || domain.ends_with('/') {
+                return Err(format!("VANITY_DOMAINS contains an invalid host: '{}'", domain).into());
+            }
+        }
+        if self.server.allowed_origins.is_empty() {
+            return Err("CORS_ALLOWED_ORIGINS must contain at least one origin".into());
+        }
+        for method in self.server.allowed_methods.iter().filter(|m| m.as_str() != "*") {
+            Method::from_str(method)
+                .map_err(|_| format!("CORS_ALLOWED_METHODS contains an invalid method: '{}'", method))?;
+        }
+        for header in self.server.allowed_headers.iter().filter(|h| h.as_str() != "*") {
+            HeaderName::from_str(header)
+                .map_err(|_| format!("CORS_ALLOWED_HEADERS contains an invalid header: '{}'", header))?;
+        }
+
+        if self.storage.path.trim().is_empty() {
+            return Err("STORAGE_PATH must not be empty".into());
+        }
+        if self.storage.cache_size_mb == 0 {
+            return Err("STORAGE_CACHE_SIZE_MB must be greater than 0".into());
+        }
+
+        if let Some(directive) = self.logging.level.split(',').map(str::trim).find(|d| !is_valid_log_directive(d)) {
+            return Err(format!(
+                "RUST_LOG contains an unrecognized log level: '{}', expected one of {}",
+                directive,
+                LOG_LEVELS.join(", ")
+            ).into());
+        }
+
+        let code_length = self.shortener.code_length;
+        if !(MIN_CODE_LENGTH..=MAX_CODE_LENGTH).contains(&code_length) {
+            return Err(format!(
+                "CODE_LENGTH must be between {} and {}, got {}",
+                MIN_CODE_LENGTH, MAX_CODE_LENGTH, code_length
+            ).into());
+        }
+
+        if self.shortener.alias_min_length == 0 {
+            return Err("ALIAS_MIN_LEN must be greater than 0".into());
+        }
+        if self.shortener.alias_min_length > self.shortener.alias_max_length {
+            return Err(format!(
+                "ALIAS_MIN_LEN must not exceed ALIAS_MAX_LEN, got {} and {}",
+                self.shortener.alias_min_length, self.shortener.alias_max_length
+            ).into());
+        }
+
+        if self.shortener.signed_codes && self.security.code_signing_secret.is_none() {
+            return Err("CODE_SIGNING_SECRET must be set when SIGNED_CODES is true".into());
+        }
+        // Checksums are located by the fixed code length, which sequential codes don't have
+        if self.shortener.signed_codes && self.shortener.code_strategy == CodeStrategy::Sequential {
+            return Err("SIGNED_CODES can't be combined with CODE_STRATEGY=sequential".into());
+        }
+
+        // Generated codes must also be valid aliases and safe to put in a URL path unescaped
+        let alphabet = &self.shortener.code_alphabet;
+        if alphabet.is_empty() {
+            return Err("CODE_ALPHABET must not be empty".into());
+        }
+        if let Some(c) = alphabet.chars().find(|c| !(c.is_ascii_alphanumeric() || *c == '-' || *c == '_')) {
+            return Err(format!(
+                "CODE_ALPHABET may only contain ASCII letters, digits, hyphens and underscores, got '{}'", c
+            ).into());
+        }
+        if alphabet.chars().enumerate().any(|(i, c)| alphabet[..i].contains(c)) {
+            return Err("CODE_ALPHABET must not contain duplicate characters".into());
+        }
+        if self.shortener.code_strategy == CodeStrategy::Sequential && alphabet.len() < 2 {
+            return Err("CODE_ALPHABET must have at least 2 characters with CODE_STRATEGY=sequential".into());
+        }
+
+        if self.shortener.allowed_schemes.is_empty() {
+            return Err("ALLOWED_SCHEMES must contain at least one scheme".into());
+        }
+
+        if self.shortener.max_url_length == 0 {
+            return Err("MAX_URL_LENGTH must be greater than 0".into());
+        }
+
+        if self.shortener.reachability_timeout_ms == 0 {
+            return Err("REACHABILITY_TIMEOUT_MS must be greater than 0".into());
+        }
+
+        if !self.storage.flush_on_write && self.storage.flush_interval_ms == 0 {
+            return Err(
+                "STORAGE_FLUSH_INTERVAL_MS must be greater than 0 when STORAGE_FLUSH_ON_WRITE is false, \
+                 otherwise writes are only made durable by batch endpoints and shutdown".into()
+            );
+        }
+
+        if self.storage.max_retries > MAX_STORAGE_RETRIES {
+            return Err(format!(
+                "STORAGE_MAX_RETRIES must be at most {}, got {}",
+                MAX_STORAGE_RETRIES, self.storage.max_retries
+            ).into());
+        }
+
+        if self.cleanup.interval_secs == 0 {
+            return Err("CLEANUP_INTERVAL_SECS must be greater than 0".into());
+        }
+
+        if let Some(policy) = &self.headers.referrer_policy {
+            HeaderValue::from_str(policy)
+                .map_err(|_| format!("REFERRER_POLICY is not a valid header value: '{}'", policy))?;
+        }
+        if let Some(policy) = &self.headers.content_security_policy {
+            HeaderValue::from_str(policy)
+                .map_err(|_| format!("CONTENT_SECURITY_POLICY is not a valid header value: '{}'", policy))?;
+        }
+
+        if !self.analytics.privacy_mode && self.analytics.ip_salt.is_none() {
+            return Err("ANALYTICS_IP_SALT must be set when PRIVACY_MODE is false".into());
+        }
+        if self.analytics.daily_retention_days == 0 {
+            return Err("DAILY_CLICKS_RETENTION_DAYS must be greater than 0".into());
+        }
+
+        if let Some(creation_url) = &self.webhook.creation_url {
+            let parsed = url::Url::parse(creation_url)
+                .map_err(|_| format!("CREATION_WEBHOOK_URL is not a valid URL: '{}'", creation_url))?;
+            if !matches!(parsed.scheme(), "http" | "https") {
+                return Err(format!("CREATION_WEBHOOK_URL must use http or https, got '{}'", creation_url).into());
+            }
+        }
+        if self.webhook.timeout_ms == 0 {
+            return Err("WEBHOOK_TIMEOUT_MS must be greater than 0".into());
+        }
+        if self.webhook.retries > MAX_WEBHOOK_RETRIES {
+            return Err(format!(
+                "WEBHOOK_RETRIES must be at most {}, got {}",
+                MAX_WEBHOOK_RETRIES, self.webhook.retries
+            ).into());
+        }
+
+        let redirect_status = self.shortener.redirect_status;
+        if redirect_status != 301 && redirect_status != 302 {
+            return Err(format!("REDIRECT_STATUS must be 301 or 302, got '{}'", redirect_status).into());
+        }
+
+        Ok(())
+    }
+
     pub fn socket_addr(&self) -> SocketAddr {
         SocketAddr::new(self.server.host, self.server.port)
     }
 
-    pub fn server_url(&self) -> String {
-        format!("http://{}", self.socket_addr())
+    pub fn tls_enabled(&self) -> bool {
+        self.server.tls_cert_path.is_some() && self.server.tls_key_path.is_some()
+    }
+
+    /// Scheme the server is reached with: `https` whenever it terminates TLS itself.
+    pub fn scheme(&self) -> &str {
+        if self.tls_enabled() {
+            "https"
+        } else {
+            &self.server.scheme
+        }
+    }
+
+    /// Base URL derived from the bind address, bracketing IPv6 hosts as RFC 3986 requires.
+    /// `None` when listening on a Unix socket, which has no URL of its own.
+    pub fn server_url(&self) -> Option<String> {
+        if self.server.unix_socket.is_some() {
+            return None;
+        }
+        let host = match self.server.host {
+            IpAddr::V4(ip) => ip.to_string(),
+            IpAddr::V6(ip) => format!("[{}]", ip),
+        };
+        Some(format!("{}://{}:{}", self.scheme(), host, self.server.port))
+    }
+
+    /// Base URL short links are published under: `public_base_url` when set, else `server_url`.
+    pub fn public_url(&self) -> String {
+        self.server
+            .public_base_url
+            .clone()
+            .or_else(|| self.server_url())
+            .expect("validate requires PUBLIC_BASE_URL with a Unix socket")
+    }
+
+    /// Base URL for a vanity domain, using the same scheme as `public_url`.
+    pub fn vanity_url(&self, domain: &str) -> String {
+        let public_url = self.public_url();
+        let scheme = public_url.split_once("://").map_or(self.scheme(), |(scheme, _)| scheme);
+        format!("{}://{}", scheme, domain)
     }
 }
 
@@ -76,4 +975,117 @@ impl Default for Config {
     fn default() -> Self {
         Self::new().expect("Failed to load default configuration")
     }
-} 
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn default_config() -> Config {
+        toml::from_str("").expect("default config")
+    }
+
+    #[test]
+    fn server_url_brackets_ipv6_hosts() {
+        let mut config = default_config();
+        config.server.host = "::1".parse().unwrap();
+        assert_eq!(config.server_url().as_deref(), Some("http://[::1]:8080"));
+
+        config.server.scheme = "https".to_string();
+        let url = url::Url::parse(&config.public_url()).unwrap();
+        assert_eq!(url.scheme(), "https");
+        assert_eq!(url.host(), Some(url::Host::Ipv6("::1".parse().unwrap())));
+    }
+
+    fn validation_error(change: impl FnOnce(&mut Config)) -> String {
+        let mut config = default_config();
+        change(&mut config);
+        config.validate().expect_err("config should be rejected").to_string()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        default_config().validate().unwrap();
+    }
+
+    #[test]
+    fn alias_length_bounds_must_be_ordered() {
+        assert!(validation_error(|config| config.shortener.alias_min_length = 0).contains("ALIAS_MIN_LEN"));
+        assert!(validation_error(|config| {
+            config.shortener.alias_min_length = 9;
+            config.shortener.alias_max_length = 8;
+        })
+        .contains("must not exceed ALIAS_MAX_LEN"));
+
+        let mut config = default_config();
+        config.shortener.alias_min_length = 8;
+        config.shortener.alias_max_length = 8;
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_zero_workers() {
+        assert!(validation_error(|config| config.server.workers = 0).contains("SERVER_WORKERS"));
+    }
+
+    #[test]
+    fn rejects_zero_cache_size() {
+        assert!(validation_error(|config| config.storage.cache_size_mb = 0).contains("STORAGE_CACHE_SIZE_MB"));
+    }
+
+    #[test]
+    fn rejects_empty_storage_path() {
+        assert!(validation_error(|config| config.storage.path = "  ".to_string()).contains("STORAGE_PATH"));
+    }
+
+    #[test]
+    fn rejects_unknown_log_levels() {
+        assert!(validation_error(|config| config.logging.level = "loud".to_string()).contains("RUST_LOG"));
+        assert!(validation_error(|config| config.logging.level = "info,actix_web=chatty".to_string()).contains("RUST_LOG"));
+    }
+
+    #[test]
+    fn unix_socket_needs_a_public_base_url() {
+        let error = validation_error(|config| config.server.unix_socket = Some("/tmp/shortener.sock".to_string()));
+        assert!(error.contains("PUBLIC_BASE_URL"));
+
+        let mut config = default_config();
+        config.server.unix_socket = Some("/tmp/shortener.sock".to_string());
+        config.server.public_base_url = Some("https://sho.rt".to_string());
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn explicit_tcp_address_conflicts_with_unix_socket_even_at_defaults() {
+        let path = std::env::temp_dir().join(format!("config-test-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[server]\nhost = \"127.0.0.1\"\nunix_socket = \"/tmp/shortener.sock\"\npublic_base_url = \"https://sho.rt\"\n",
+        )
+        .unwrap();
+        let config = Config::from_file(&path);
+        let _ = fs::remove_file(&path);
+
+        let error = config.unwrap().validate().expect_err("config should be rejected").to_string();
+        assert!(error.contains("SERVER_UNIX_SOCKET"));
+    }
+
+    #[test]
+    fn env_or_keeps_current_when_unset() {
+        env::remove_var("TEST_ENV_OR_UNSET");
+        assert_eq!(env_or("TEST_ENV_OR_UNSET", 6usize), Ok(6));
+    }
+
+    #[test]
+    fn env_or_parses_set_values() {
+        env::set_var("TEST_ENV_OR_BOOL", "true");
+        assert_eq!(env_or("TEST_ENV_OR_BOOL", false), Ok(true));
+    }
+
+    #[test]
+    fn env_or_rejects_malformed_values() {
+        env::set_var("TEST_ENV_OR_LENGTH", "abc");
+        assert!(env_or("TEST_ENV_OR_LENGTH", 6usize).is_err());
+        env::set_var("TEST_ENV_OR_FLAG", "yes");
+        assert!(env_or("TEST_ENV_OR_FLAG", false).is_err());
+    }
+}
@@ -0,0 +1,19 @@
+use actix_web::{guard::GuardContext, http::header, HttpResponse, Responder};
+
+const INDEX_HTML: &str = include_str!("../../static/index.html");
+
+/// Matches browsers asking for a page, so API clients hitting `/` keep getting a plain 404.
+pub fn accepts_html(ctx: &GuardContext) -> bool {
+    ctx.head()
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// Serves the embedded shortening form, which posts to `/api/v1/shorten`.
+pub async fn index() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(INDEX_HTML)
+}
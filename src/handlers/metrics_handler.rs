@@ -0,0 +1,25 @@
+use actix_web::{web, HttpResponse, Responder};
+use prometheus::TEXT_FORMAT;
+use tracing::error;
+use crate::metrics::Metrics;
+use crate::services::url_service::UrlService;
+
+pub async fn metrics(metrics: web::Data<Metrics>, service: web::Data<UrlService>) -> impl Responder {
+    // A failed size read leaves the last value in place rather than failing the scrape
+    match service.storage_size().await {
+        Ok(size) => metrics.storage_size_bytes.set(i64::try_from(size).unwrap_or(i64::MAX)),
+        Err(e) => error!("Failed to read storage size: {}", e),
+    }
+
+    match metrics.render() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(TEXT_FORMAT)
+            .body(body),
+        Err(e) => {
+            error!("Failed to encode metrics: {}", e);
+            HttpResponse::InternalServerError().json(serde_json::json!({
+                "error": "Failed to encode metrics"
+            }))
+        }
+    }
+}
@@ -1 +1,6 @@
-pub mod url_handler; 
\ No newline at end of file
+pub mod url_handler;
+pub mod metrics_handler;
+pub mod version_handler;
+pub mod frontend_handler;
+pub mod openapi_handler;
+pub mod site_handler;
@@ -0,0 +1,135 @@
+use actix_web::{HttpResponse, Responder};
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+use crate::handlers::{url_handler, version_handler};
+use crate::middleware::api_key::API_KEY_HEADER;
+
+/// OpenAPI 3 description of the API, generated from the handlers and their request and
+/// response types. The version is taken from `Cargo.toml`.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Blazing Fast URL Shortener",
+        description = "Creates, resolves and manages short links. Write requests under /api/ and the listing, top and export reads require an X-API-Key header when API keys are configured, and changing or deleting a link created with a management token also requires that token in X-Manage-Token.",
+        license(name = "MIT"),
+    ),
+    paths(
+        url_handler::redirect,
+        url_handler::shorten_url,
+        url_handler::shorten_batch,
+        url_handler::alias_available,
+        url_handler::expand_url,
+        url_handler::expand_batch,
+        url_handler::update_url,
+        url_handler::delete_url,
+        url_handler::restore_url,
+        url_handler::qr_code,
+        url_handler::url_stats,
+        url_handler::daily_stats,
+        url_handler::list_urls,
+        url_handler::top_urls,
+        url_handler::count_urls,
+        url_handler::export_urls,
+        url_handler::import_urls,
+        url_handler::compact_storage,
+        url_handler::health_check,
+        version_handler::version,
+        openapi,
+    ),
+    components(schemas(
+        url_handler::ShortenRequest,
+        url_handler::ShortenResponse,
+        url_handler::UpdateRequest,
+        url_handler::BatchShortenRequest,
+        url_handler::BatchShortenItem,
+        url_handler::ExpandResponse,
+        url_handler::BatchExpandRequest,
+        url_handler::BatchExpandItem,
+        url_handler::UrlStatsResponse,
+        url_handler::DailyClicksItem,
+        url_handler::UrlListItem,
+        url_handler::UrlListResponse,
+        url_handler::TopUrlsResponse,
+        url_handler::CountResponse,
+        url_handler::CompactResponse,
+        url_handler::AliasAvailability,
+        url_handler::DestinationResponse,
+        url_handler::StatusResponse,
+        url_handler::ImportItem,
+        url_handler::ImportSummary,
+        url_handler::ErrorResponse,
+        url_handler::AliasTakenResponse,
+        version_handler::VersionResponse,
+    )),
+    modifiers(&SecuritySchemes),
+)]
+pub struct ApiDoc;
+
+/// Registers the API key and management token headers the operations refer to.
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme("ApiKey", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(API_KEY_HEADER))));
+        components.add_security_scheme(
+            "ManageToken",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new(url_handler::MANAGE_TOKEN_HEADER))),
+        );
+    }
+}
+
+/// Serves this document.
+#[utoipa::path(
+    get,
+    path = "/api/v1/openapi.json",
+    tag = "Service",
+    responses(
+        (status = 200, description = "OpenAPI document", content_type = "application/json"),
+    ),
+)]
+pub async fn openapi() -> impl Responder {
+    HttpResponse::Ok().json(ApiDoc::openapi())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collect_refs<'a>(value: &'a serde_json::Value, refs: &mut Vec<&'a str>) {
+        match value {
+            serde_json::Value::Object(map) => {
+                if let Some(serde_json::Value::String(target)) = map.get("$ref") {
+                    refs.push(target);
+                }
+                map.values().for_each(|value| collect_refs(value, refs));
+            }
+            serde_json::Value::Array(items) => items.iter().for_each(|value| collect_refs(value, refs)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn document_covers_the_api() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        assert_eq!(document["info"]["version"], env!("CARGO_PKG_VERSION"));
+        for path in ["/{short_code}", "/api/v1/shorten", "/api/v1/{short_code}", "/api/v1/import", "/api/v1/openapi.json"] {
+            assert!(document["paths"][path].is_object(), "{} is not documented", path);
+        }
+        assert!(document["paths"]["/api/v1/{short_code}"]["delete"].is_object());
+        assert_eq!(document["components"]["securitySchemes"]["ApiKey"]["name"], API_KEY_HEADER);
+        assert_eq!(document["components"]["schemas"]["ShortenRequest"]["required"], serde_json::json!(["url"]));
+    }
+
+    #[test]
+    fn every_schema_reference_resolves() {
+        let document = serde_json::to_value(ApiDoc::openapi()).unwrap();
+        let mut refs = Vec::new();
+        collect_refs(&document, &mut refs);
+        assert!(!refs.is_empty());
+        for target in refs {
+            let name = target.strip_prefix("#/components/schemas/").expect("local schema reference");
+            assert!(document["components"]["schemas"][name].is_object(), "{} is not registered", name);
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use actix_web::{web, HttpResponse, Responder};
+use std::path::{Path, PathBuf};
+use tracing::error;
+use crate::config::Config;
+
+/// Icon served at `/favicon.ico`, read once at startup from `favicon_path`.
+pub struct Favicon {
+    content_type: &'static str,
+    bytes: Vec<u8>,
+}
+
+impl Favicon {
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        let content_type = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("png") => "image/png",
+            Some("svg") => "image/svg+xml",
+            Some("gif") => "image/gif",
+            _ => "image/x-icon",
+        };
+        Ok(Self {
+            content_type,
+            bytes: std::fs::read(path)?,
+        })
+    }
+}
+
+/// Serves the crawler policy; by default crawlers are kept off every short link.
+pub async fn robots(config: web::Data<Config>) -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("text/plain; charset=utf-8")
+        .body(config.server.robots_txt.clone())
+}
+
+/// Answers browsers' automatic icon request with the configured icon, or an empty `204`
+/// rather than a short code lookup.
+pub async fn favicon(icon: web::Data<Option<Favicon>>) -> impl Responder {
+    match icon.get_ref() {
+        Some(icon) => HttpResponse::Ok()
+            .content_type(icon.content_type)
+            .body(icon.bytes.clone()),
+        None => HttpResponse::NoContent().finish(),
+    }
+}
+
+/// Serves files below `well_known_dir` (e.g. ACME HTTP-01 challenges written by a webroot
+/// client), or a plain `404` when none is configured. Short codes are never looked up here.
+pub async fn well_known(config: web::Data<Config>, path: web::Path<String>) -> impl Responder {
+    let not_found = || HttpResponse::NotFound().content_type("text/plain; charset=utf-8").body("Not found");
+    let Some(dir) = config.server.well_known_dir.as_deref() else {
+        return not_found();
+    };
+    let Some(file) = well_known_file(dir, &path) else {
+        return not_found();
+    };
+
+    match web::block(move || std::fs::read(file)).await {
+        Ok(Ok(bytes)) => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(bytes),
+        Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => not_found(),
+        Ok(Err(e)) => {
+            error!("Failed to read well-known file: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+        Err(e) => {
+            error!("Failed to read well-known file: {}", e);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Maps a request path onto the well-known directory, refusing anything that could step
+/// outside it or reach hidden files.
+fn well_known_file(dir: &str, path: &str) -> Option<PathBuf> {
+    let mut file = PathBuf::from(dir);
+    for segment in path.split('/') {
+        let valid = !segment.is_empty()
+            && !segment.starts_with('.')
+            && segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+        if !valid {
+            return None;
+        }
+        file.push(segment);
+    }
+    Some(file)
+}
@@ -1,100 +1,1742 @@
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{http::{header::{self, Accept}, Method}, web, web::Bytes, Either, HttpMessage, HttpRequest, HttpResponse, Responder};
+use futures::{stream, StreamExt};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use url::Url;
 use serde::{Deserialize, Serialize};
-use tracing::{error, debug};
-use crate::services::url_service::{UrlService, UrlServiceError};
+use tracing::{error, debug, info, warn};
+use utoipa::{IntoParams, ToSchema};
+use crate::config::Config;
+use crate::metrics::Metrics;
+use crate::repositories::url_record::now_millis;
+use crate::services::url_service::{
+    UrlService, UrlServiceError, ClickContext, ResolvedUrl, ShortenOptions, ShortenedUrl, UrlSummary, ImportEntry, ImportOutcome,
+    MAX_DESCRIPTION_LENGTH, MAX_PAGE_SIZE, MAX_TAGS, MAX_TAG_LENGTH, MAX_TOP_LIMIT,
+};
+use crate::services::qr_service::{render_qr, QrFormat};
 
-#[derive(Deserialize)]
+
+/// Header carrying the token returned when a link was created, required to edit or delete it.
+pub const MANAGE_TOKEN_HEADER: &str = "X-Manage-Token";
+
+/// Header that makes retried shorten requests return the original response.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// Room per import entry for the field names, timestamps, counters and punctuation.
+const IMPORT_ENTRY_OVERHEAD_BYTES: usize = 1024;
+
+/// Backups are far larger than regular API bodies, so imports get their own JSON limit:
+/// `MAX_BATCH_SIZE` entries with every field at its largest, since more entries are rejected anyway.
+pub fn import_body_limit(config: &Config) -> usize {
+    // JSON escaping can grow a character of free text to six bytes (`\uXXXX`)
+    let text = (MAX_DESCRIPTION_LENGTH + MAX_TAGS * MAX_TAG_LENGTH) * 6;
+    let entry = config.shortener.max_url_length + config.shortener.alias_max_length + text + IMPORT_ENTRY_OVERHEAD_BYTES;
+    config.server.max_batch_size.saturating_mul(entry)
+}
+
+const DEFAULT_TOP_LIMIT: usize = 10;
+const DEFAULT_DAILY_DAYS: u32 = 30;
+
+/// Also accepted as `application/x-www-form-urlencoded`, so HTML forms can post directly.
+#[derive(Deserialize, ToSchema)]
 pub struct ShortenRequest {
+    /// Destination to shorten
     url: String,
+    /// Custom short code; surrounding whitespace is trimmed. 3 to 32 characters unless
+    /// `ALIAS_MIN_LEN` and `ALIAS_MAX_LEN` say otherwise
     #[serde(default)]
+    #[schema(pattern = "^[A-Za-z0-9_-]+$")]
     custom_alias: Option<String>,
+    #[serde(default)]
+    expires_in_seconds: Option<u64>,
+    /// Resolve once, then stop working
+    #[serde(default)]
+    one_time: bool,
+    #[serde(default)]
+    #[schema(minimum = 1)]
+    max_clicks: Option<u64>,
+    /// Reject destinations that don't answer a HEAD request
+    #[serde(default)]
+    verify_reachable: bool,
+    /// Configured vanity domain to publish the link under
+    #[serde(default)]
+    domain: Option<String>,
+    /// Free-form note about the link, stored encrypted
+    #[serde(default)]
+    #[schema(max_length = 500)]
+    description: Option<String>,
+    /// Labels to filter listings by; lowercased and deduplicated
+    #[serde(default)]
+    #[schema(max_items = 10)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UpdateRequest {
+    url: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchShortenRequest {
+    /// At most `MAX_BATCH_SIZE` URLs (100 by default)
+    urls: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(untagged)]
+pub enum BatchShortenItem {
+    Shortened(ShortenResponse),
+    Failed { url: String, error: String },
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ExpandResponse {
+    short_code: String,
+    original_url: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct BatchExpandRequest {
+    /// At most `MAX_BATCH_SIZE` codes (100 by default)
+    codes: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct BatchExpandItem {
+    code: String,
+    original_url: Option<String>,
+    found: bool,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UrlStatsResponse {
+    short_code: String,
+    original_url: String,
+    clicks: u64,
+    created_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    last_accessed: Option<u64>,
+    description: Option<String>,
+    tags: Vec<String>,
+    /// Omitted in privacy mode, where client IPs aren't captured.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unique_visitors: Option<u64>,
+    /// Present only with analytics enabled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    referrers: Option<BTreeMap<String, u64>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_agents: Option<BTreeMap<String, u64>>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QrQuery {
+    /// `png` (the default) or `svg`
+    #[serde(default)]
+    format: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ListQuery {
+    #[serde(default)]
+    #[param(maximum = 100, default = 100)]
+    limit: Option<usize>,
+    #[serde(default)]
+    offset: Option<usize>,
+    /// Restricts the listing to links carrying this tag.
+    #[serde(default)]
+    tag: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct TopQuery {
+    #[serde(default)]
+    #[param(maximum = 100, default = 10)]
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DailyQuery {
+    #[serde(default)]
+    #[param(default = 30)]
+    days: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct DailyClicksItem {
+    /// UTC day as `YYYY-MM-DD`
+    date: String,
+    count: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UrlListItem {
+    short_code: String,
+    original_url: String,
+    clicks: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_accessed: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    one_time: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_clicks: Option<u64>,
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
+}
+
+impl From<UrlSummary> for UrlListItem {
+    fn from(summary: UrlSummary) -> Self {
+        UrlListItem {
+            short_code: summary.short_code,
+            original_url: summary.original_url,
+            clicks: summary.clicks,
+            expires_at: summary.expires_at,
+            created_at: summary.created_at,
+            last_accessed: summary.last_accessed,
+            one_time: summary.one_time,
+            max_clicks: summary.max_clicks,
+            description: summary.description,
+            tags: summary.tags,
+        }
+    }
+}
+
+/// One element of an export file; other fields are ignored on import.
+#[derive(Deserialize, ToSchema)]
+pub struct ImportItem {
+    short_code: String,
+    original_url: String,
+    #[serde(default)]
+    expires_at: Option<u64>,
+    #[serde(default)]
+    created_at: Option<u64>,
+    #[serde(default)]
+    one_time: bool,
+    #[serde(default)]
+    max_clicks: Option<u64>,
+    /// Restored as the click count
+    #[serde(default)]
+    clicks: u64,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+}
+
+#[derive(Deserialize, ToSchema, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportMode {
+    #[default]
+    Skip,
+    Overwrite,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ImportQuery {
+    #[serde(default)]
+    #[param(inline)]
+    mode: ImportMode,
+}
+
+#[derive(Serialize, ToSchema, Default)]
+pub struct ImportSummary {
+    created: usize,
+    skipped: usize,
+    failed: usize,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct UrlListResponse {
+    total: usize,
+    limit: usize,
+    offset: usize,
+    urls: Vec<UrlListItem>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct TopUrlsResponse {
+    limit: usize,
+    urls: Vec<UrlListItem>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CountResponse {
+    total: usize,
+    size_on_disk: u64,
+}
+
+/// Storage size in bytes before and after compaction.
+#[derive(Serialize, ToSchema)]
+pub struct CompactResponse {
+    size_before: u64,
+    size_after: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AliasAvailability {
+    available: bool,
+}
+
+/// Sent instead of a redirect to clients that prefer JSON.
+#[derive(Serialize, ToSchema)]
+pub struct DestinationResponse {
+    original_url: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StatusResponse {
+    /// `ok`, or `degraded` while storage can't be read
+    status: &'static str,
+}
+
+/// Body of every error response.
+#[derive(Serialize, ToSchema)]
+pub struct ErrorResponse {
+    error: String,
+    /// Machine-readable reason, where provided: `not_found`, `expired` or `deleted`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    code: Option<&'static str>,
+}
+
+impl ErrorResponse {
+    fn new(error: impl Into<String>) -> Self {
+        Self { error: error.into(), code: None }
+    }
+
+    fn with_code(error: impl Into<String>, code: &'static str) -> Self {
+        Self { error: error.into(), code: Some(code) }
+    }
+}
+
+/// Returned when a custom alias is already used for a different URL. The current
+/// destination is deliberately left out so aliases can't be used to peek at others' links.
+#[derive(Serialize, ToSchema)]
+pub struct AliasTakenResponse {
+    error: &'static str,
+    /// Always `alias_taken`
+    code: &'static str,
+    alias: String,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 pub struct ShortenResponse {
     short_code: String,
     original_url: String,
     short_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<u64>,
+    /// Present and true for one-time links
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    one_time: bool,
+    /// Secret for updating or deleting the link; only returned when it is created
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manage_token: Option<String>,
+}
+
+impl From<ShortenedUrl> for ShortenResponse {
+    fn from(shortened: ShortenedUrl) -> Self {
+        Self {
+            short_code: shortened.short_code,
+            original_url: shortened.original_url,
+            short_url: shortened.full_short_url,
+            expires_at: shortened.expires_at,
+            created_at: shortened.created_at,
+            one_time: shortened.one_time,
+            manage_token: shortened.manage_token,
+        }
+    }
+}
+
+/// Liveness probe: answering at all means the process is up, so storage isn't touched.
+pub async fn liveness() -> impl Responder {
+    HttpResponse::Ok().json(StatusResponse { status: "ok" })
+}
+
+/// Readiness probe, also served as `/api/v1/health`: fails while storage can't be read.
+#[utoipa::path(
+    get,
+    path = "/api/v1/health",
+    tag = "Service",
+    responses(
+        (status = 200, description = "Storage is readable", body = StatusResponse),
+        (status = 503, description = "Storage can't be read", body = StatusResponse),
+    ),
+)]
+pub async fn health_check(service: web::Data<UrlService>) -> impl Responder {
+    match service.check_storage().await {
+        Ok(()) => HttpResponse::Ok().json(StatusResponse { status: "ok" }),
+        Err(e) => {
+            error!("Health check failed: {}", e);
+            HttpResponse::ServiceUnavailable().json(StatusResponse { status: "degraded" })
+        }
+    }
 }
 
-pub async fn health_check() -> impl Responder {
-    HttpResponse::Ok().json(serde_json::json!({ "status": "ok" }))
+/// The client's address: the forwarded one when the peer is a trusted proxy (or a local
+/// process on the Unix socket), otherwise the peer address, so clients can't spoof it.
+fn client_ip(req: &HttpRequest, config: &Config) -> Option<String> {
+    let peer = req.peer_addr().map(|addr| addr.ip());
+    if peer.is_none_or(|ip| config.server.trusted_proxies.contains(&ip)) {
+        return req.connection_info().realip_remote_addr().map(String::from);
+    }
+    peer.map(|ip| ip.to_string())
 }
 
+/// Creates a short link.
+///
+/// Returns 200 with the existing link when the same URL (or the same alias and URL) was already shortened.
+#[utoipa::path(
+    post,
+    path = "/api/v1/shorten",
+    tag = "Links",
+    request_body = ShortenRequest,
+    params(("Idempotency-Key" = Option<String>, Header, description = "Repeating a request with the same key replays the first response")),
+    security((), ("ApiKey" = [])),
+    responses(
+        (status = 200, description = "Existing link returned", body = ShortenResponse),
+        (status = 201, description = "Link created", body = ShortenResponse, headers(("Location" = String, description = "URL of the created short link"))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 403, description = "Destination domain is not allowed", body = ErrorResponse),
+        (status = 409, description = "Alias is already used for a different URL", body = AliasTakenResponse),
+        (status = 422, description = "Destination is unreachable, or the Idempotency-Key was used for a different request", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
 pub async fn shorten_url(
     service: web::Data<UrlService>,
-    req: web::Json<ShortenRequest>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    req: HttpRequest,
+    body: Either<web::Json<ShortenRequest>, web::Form<ShortenRequest>>,
 ) -> impl Responder {
-    debug!("Shortening URL: {}", req.url);
-    match service.shorten_url(req.url.clone(), req.custom_alias.clone()).await {
+    let _timer = metrics.shorten_duration_seconds.start_timer();
+    // HTML forms can post directly; both body types carry the same fields
+    let body = match body {
+        Either::Left(json) => json.into_inner(),
+        Either::Right(form) => form.into_inner(),
+    };
+    debug!(url = %body.url, client_ip = client_ip(&req, &config).as_deref().unwrap_or("unknown"), "Shortening URL");
+    let idempotency_key = header_str(&req, IDEMPOTENCY_KEY_HEADER);
+    if idempotency_key.is_some_and(|key| key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH) {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("Idempotency-Key must be 1 to {} characters", MAX_IDEMPOTENCY_KEY_LENGTH)));
+    }
+    let options = ShortenOptions {
+        // An empty form field means no alias rather than an invalid one
+        custom_alias: body.custom_alias.filter(|alias| !alias.is_empty()),
+        expires_in_seconds: body.expires_in_seconds,
+        one_time: body.one_time,
+        max_clicks: body.max_clicks,
+        verify_reachable: body.verify_reachable,
+        domain: body.domain.filter(|domain| !domain.is_empty()),
+        description: body.description.filter(|description| !description.is_empty()),
+        tags: body.tags,
+    };
+    match service.shorten_url(body.url, options, idempotency_key).await {
         Ok(shortened) => {
-            debug!("Successfully shortened URL to: {}", shortened.short_code);
-            HttpResponse::Ok().json(ShortenResponse {
-                short_code: shortened.short_code,
-                original_url: shortened.original_url,
-                short_url: shortened.full_short_url,
-            })
+            debug!(short_code = %shortened.short_code, "Successfully shortened URL");
+            metrics.shortens_total.inc();
+            if !shortened.created {
+                return HttpResponse::Ok().json(ShortenResponse::from(shortened));
+            }
+
+            HttpResponse::Created()
+                .append_header(("Location", shortened.full_short_url.clone()))
+                .json(ShortenResponse::from(shortened))
         },
-        Err(UrlServiceError::InvalidUrl(_)) => {
-            HttpResponse::BadRequest().json(serde_json::json!({
-                "error": "Invalid URL format"
-            }))
+        Err(UrlServiceError::InvalidUrl(msg)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new(msg))
+        }
+        Err(UrlServiceError::SelfReference(_)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new("URL points back at this shortener"))
         }
         Err(UrlServiceError::InvalidAlias(msg)) => {
-            HttpResponse::BadRequest().json(serde_json::json!({
-                "error": format!("Invalid alias: {}", msg)
-            }))
+            HttpResponse::BadRequest().json(ErrorResponse::new(format!("Invalid alias: {}", msg)))
+        }
+        Err(UrlServiceError::DomainNotAllowed(host)) => {
+            HttpResponse::Forbidden().json(ErrorResponse::new(format!("Domain '{}' is not allowed", host)))
+        }
+        Err(UrlServiceError::InvalidDomain(domain)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new(format!("'{}' is not a configured short link domain", domain)))
+        }
+        Err(UrlServiceError::InvalidDescription(msg)) | Err(UrlServiceError::InvalidTags(msg)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new(msg))
+        }
+        Err(UrlServiceError::IdempotencyKeyReused(_)) => {
+            HttpResponse::UnprocessableEntity().json(ErrorResponse::new("Idempotency-Key was already used for a different request"))
         }
         Err(UrlServiceError::AliasExists(alias)) => {
-            HttpResponse::Conflict().json(serde_json::json!({
-                "error": format!("Alias '{}' is already taken", alias)
-            }))
+            HttpResponse::Conflict().json(AliasTakenResponse {
+                error: "Alias is already taken",
+                code: "alias_taken",
+                alias,
+            })
+        }
+        Err(UrlServiceError::Unreachable(msg)) => {
+            HttpResponse::UnprocessableEntity().json(ErrorResponse::new(format!("Destination is unreachable: {}", msg)))
         }
         Err(UrlServiceError::EncryptionError(e)) => {
             error!("Encryption error while shortening URL: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to secure URL data"
-            }))
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to secure URL data"))
         }
         Err(e) => {
             error!("Failed to shorten URL: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to shorten URL"
-            }))
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to shorten URL"))
+        }
+    }
+}
+
+/// Creates many short links, reporting one result per URL in request order.
+#[utoipa::path(
+    post,
+    path = "/api/v1/shorten/batch",
+    tag = "Links",
+    request_body = BatchShortenRequest,
+    security((), ("ApiKey" = [])),
+    responses(
+        (status = 200, description = "One result per URL, in request order", body = [BatchShortenItem]),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn shorten_batch(
+    service: web::Data<UrlService>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    req: web::Json<BatchShortenRequest>,
+) -> impl Responder {
+    let urls = req.into_inner().urls;
+    if urls.len() > config.server.max_batch_size {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("At most {} URLs can be shortened per request", config.server.max_batch_size)));
+    }
+    debug!("Shortening batch of {} URLs", urls.len());
+
+    match service.shorten_batch(urls.clone()).await {
+        Ok(results) => {
+            metrics.shortens_total.inc_by(results.iter().filter(|result| result.is_ok()).count() as u64);
+            let items: Vec<BatchShortenItem> = urls
+                .into_iter()
+                .zip(results)
+                .map(|(url, result)| match result {
+                    Ok(shortened) => BatchShortenItem::Shortened(shortened.into()),
+                    Err(UrlServiceError::InvalidUrl(msg)) => BatchShortenItem::Failed {
+                        url,
+                        error: msg,
+                    },
+                    Err(UrlServiceError::DomainNotAllowed(host)) => BatchShortenItem::Failed {
+                        url,
+                        error: format!("Domain '{}' is not allowed", host),
+                    },
+                    Err(UrlServiceError::SelfReference(_)) => BatchShortenItem::Failed {
+                        url,
+                        error: "URL points back at this shortener".to_string(),
+                    },
+                    Err(e) => {
+                        error!("Failed to shorten URL in batch: {}", e);
+                        BatchShortenItem::Failed {
+                            url,
+                            error: "Failed to shorten URL".to_string(),
+                        }
+                    }
+                })
+                .collect();
+            HttpResponse::Ok().json(items)
+        }
+        Err(e) => {
+            error!("Failed to persist URL batch: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to shorten URLs"))
         }
     }
 }
 
+/// Appends the path that followed the short code to the destination's path, so a code
+/// can act as a prefix. The destination's own query and fragment are kept.
+fn append_path(destination: String, tail: &str) -> String {
+    let tail = tail.trim_start_matches('/');
+    if tail.is_empty() {
+        return destination;
+    }
+    let Ok(mut url) = Url::parse(&destination) else {
+        return destination;
+    };
+    if url.cannot_be_a_base() {
+        return destination;
+    }
+
+    let path = format!("{}/{}", url.path().trim_end_matches('/'), tail);
+    url.set_path(&path);
+    url.to_string()
+}
+
+/// Merges the incoming query string into the destination. Incoming parameters win
+/// over destination parameters with the same key; without a query string the
+/// destination is returned untouched.
+fn forward_query(destination: String, query: &str) -> String {
+    if query.is_empty() {
+        return destination;
+    }
+    let Ok(mut url) = Url::parse(&destination) else {
+        return destination;
+    };
+
+    let incoming: Vec<(String, String)> = url::form_urlencoded::parse(query.as_bytes())
+        .into_owned()
+        .collect();
+    let kept: Vec<(String, String)> = url
+        .query_pairs()
+        .into_owned()
+        .filter(|(key, _)| !incoming.iter().any(|(incoming_key, _)| incoming_key == key))
+        .collect();
+
+    url.query_pairs_mut()
+        .clear()
+        .extend_pairs(kept)
+        .extend_pairs(incoming);
+    url.to_string()
+}
+
+/// Query parameter that asks for the preview page instead of an immediate redirect.
+const PREVIEW_PARAM: &str = "preview";
+
+/// Splits the `preview` flag off the query string, returning whether it was present
+/// and the remaining query to forward.
+fn take_preview_flag(query: &str) -> (bool, String) {
+    let mut preview = false;
+    let mut remaining = url::form_urlencoded::Serializer::new(String::new());
+    for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+        if key == PREVIEW_PARAM {
+            preview = true;
+        } else {
+            remaining.append_pair(&key, &value);
+        }
+    }
+    if !preview {
+        // Forward the original query untouched rather than a re-encoded copy
+        return (false, query.to_string());
+    }
+    (true, remaining.finish())
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Minimal page that shows where a short link leads, with a link to continue there.
+fn preview_page(destination: &str) -> HttpResponse {
+    let destination = escape_html(destination);
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Link preview</title>\n</head>\n<body>\n<p>This link leads to:</p>\n<p><code>{0}</code></p>\n<p><a href=\"{0}\" rel=\"noreferrer noopener\">Continue</a></p>\n</body>\n</html>\n",
+            destination
+        ))
+}
+
+/// Whether the client ranks `application/json` above `text/html`. Wildcards don't count
+/// for either, so clients sending only `*/*` still get a redirect.
+fn prefers_json(req: &HttpRequest) -> bool {
+    let Some(accept) = req.get_header::<Accept>() else {
+        return false;
+    };
+    accept
+        .ranked()
+        .iter()
+        .find(|mime| matches!(mime.essence_str(), "application/json" | "text/html"))
+        .is_some_and(|mime| mime.essence_str() == "application/json")
+}
+
+/// Follows a short link.
+///
+/// Redirects to the destination, or renders a preview page when `?preview` is given.
+/// Any path after the short code is appended to the destination, and clients that
+/// prefer JSON get the destination as `{ "original_url": ... }` instead of a redirect.
+/// HEAD answers the same way without counting a click or consuming a one-time link.
+#[utoipa::path(
+    get,
+    path = "/{short_code}",
+    tag = "Redirect",
+    params(("short_code" = String, Path, description = "Short code or custom alias")),
+    responses(
+        (status = 200, description = "Destination for clients preferring JSON, or the preview page", content(("application/json" = DestinationResponse), ("text/html" = String))),
+        (status = 301, description = "Permanent redirect to the destination", headers(("Location" = String, description = "Destination"))),
+        (status = 302, description = "Redirect to the destination", headers(("Location" = String, description = "Destination"))),
+        (status = 304, description = "Cached permanent redirect is still current"),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 410, description = "Link expired or was deleted", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
 pub async fn redirect(
     service: web::Data<UrlService>,
-    path: web::Path<String>,
+    config: web::Data<Config>,
+    metrics: web::Data<Metrics>,
+    path: web::Path<(String, String)>,
+    req: HttpRequest,
 ) -> impl Responder {
-    let short_url = path.into_inner();
-    debug!("Redirecting short URL: {}", short_url);
-    
-    match service.get_url(&short_url).await {
-        Ok(url) => {
-            debug!("Successfully resolved URL: {}", short_url);
-            HttpResponse::Found()
-                .append_header(("Location", url))
-                .finish()
+    let (short_url, tail) = path.into_inner();
+    let (preview, query) = take_preview_flag(req.query_string());
+    let ip = client_ip(&req, &config);
+
+    // Link checkers probe with HEAD; answer like the redirect would without counting a
+    // click or consuming a one-time link
+    if req.method() == Method::HEAD {
+        debug!(short_code = %short_url, client_ip = ip.as_deref().unwrap_or("unknown"), "Checking short URL");
+        return match service.peek_link(&short_url).await {
+            Ok(link) => {
+                let cache_secs = redirect_cache_secs(&config, &link);
+                let destination = forward_query(append_path(link.url, &tail), &query);
+                not_modified_response(&req, &destination, cache_secs)
+                    .unwrap_or_else(|| redirect_response(&config, destination, cache_secs))
+            }
+            Err(e) => redirect_error_response(e, &metrics),
+        };
+    }
+
+    // A preview only shows the destination; the "Continue" link is what consumes a one-time link
+    if preview {
+        debug!(short_code = %short_url, client_ip = ip.as_deref().unwrap_or("unknown"), "Previewing short URL");
+        return match service.peek_url(&short_url).await {
+            Ok(url) => preview_page(&forward_query(append_path(url, &tail), &query)),
+            Err(e) => redirect_error_response(e, &metrics),
+        };
+    }
+
+    // A cached redirect that is still current is confirmed before anything is counted. Only
+    // cacheable links qualify, so one-time and click-limited links always take the full path.
+    if req.headers().contains_key(header::IF_NONE_MATCH) && !prefers_json(&req) {
+        if let Ok(link) = service.peek_link(&short_url).await {
+            let cache_secs = redirect_cache_secs(&config, &link);
+            let destination = forward_query(append_path(link.url, &tail), &query);
+            if let Some(response) = not_modified_response(&req, &destination, cache_secs) {
+                return response;
+            }
+        }
+    }
+
+    debug!(short_code = %short_url, client_ip = ip.as_deref().unwrap_or("unknown"), "Redirecting short URL");
+    let context = ClickContext {
+        client_ip: ip.as_deref(),
+        referrer: header_str(&req, header::REFERER),
+        user_agent: header_str(&req, header::USER_AGENT),
+    };
+    match service.resolve_click(&short_url, &context).await {
+        Ok(link) => {
+            debug!(short_code = %short_url, "Successfully resolved URL");
+            metrics.redirects_total.inc();
+            let cache_secs = redirect_cache_secs(&config, &link);
+            let destination = forward_query(append_path(link.url, &tail), &query);
+            if prefers_json(&req) {
+                return HttpResponse::Ok()
+                    .append_header((header::VARY, "Accept"))
+                    .json(DestinationResponse { original_url: destination });
+            }
+
+            redirect_response(&config, destination, cache_secs)
         },
-        Err(UrlServiceError::NotFound(_)) => {
-            HttpResponse::NotFound().json(serde_json::json!({
-                "error": "URL not found"
-            }))
+        Err(e) => redirect_error_response(e, &metrics),
+    }
+}
+
+/// How long a redirect to `link` may be cached. Only 301s are cached, and only with
+/// `REDIRECT_CACHE_SECS` set; one-time and click-limited links never are, since every visit
+/// must reach the service, and expiring links at most until they expire.
+fn redirect_cache_secs(config: &Config, link: &ResolvedUrl) -> u64 {
+    if config.shortener.redirect_status != 301 || link.one_time || link.max_clicks.is_some() {
+        return 0;
+    }
+    let cache_secs = config.shortener.redirect_cache_secs;
+    match link.expires_at {
+        Some(expires_at) => cache_secs.min(expires_at.saturating_sub(now_millis()) / 1000),
+        None => cache_secs,
+    }
+}
+
+/// Builds the redirect. Cacheable ones carry an ETag of the destination, so a revalidating
+/// client can be answered by `not_modified_response`.
+fn redirect_response(config: &Config, destination: String, cache_secs: u64) -> HttpResponse {
+    if cache_secs == 0 {
+        let mut response = match config.shortener.redirect_status {
+            301 => HttpResponse::MovedPermanently(),
+            _ => HttpResponse::Found(),
+        };
+        return response
+            .append_header(("Location", destination))
+            .append_header((header::VARY, "Accept"))
+            .append_header((header::CACHE_CONTROL, "no-cache"))
+            .finish();
+    }
+
+    let etag = destination_etag(&destination);
+    HttpResponse::MovedPermanently()
+        .append_header(("Location", destination))
+        .append_header((header::VARY, "Accept"))
+        .append_header((header::CACHE_CONTROL, format!("public, max-age={}", cache_secs)))
+        .append_header(header::ETag(etag))
+        .finish()
+}
+
+/// `304 Not Modified` for a cacheable redirect whose ETag the client already holds.
+fn not_modified_response(req: &HttpRequest, destination: &str, cache_secs: u64) -> Option<HttpResponse> {
+    if cache_secs == 0 {
+        return None;
+    }
+    let etag = destination_etag(destination);
+    let not_modified = match req.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => true,
+        Some(header::IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+    not_modified.then(|| {
+        HttpResponse::NotModified()
+            .append_header(("Location", destination))
+            .append_header((header::VARY, "Accept"))
+            .append_header((header::CACHE_CONTROL, format!("public, max-age={}", cache_secs)))
+            .append_header(header::ETag(etag))
+            .finish()
+    })
+}
+
+fn destination_etag(destination: &str) -> header::EntityTag {
+    let digest = ring::digest::digest(&ring::digest::SHA256, destination.as_bytes());
+    let tag = digest.as_ref()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    header::EntityTag::new_strong(tag)
+}
+
+/// Like `resolve_error_response`, also counting unknown codes in the redirect metrics.
+fn redirect_error_response(error: UrlServiceError, metrics: &Metrics) -> HttpResponse {
+    if matches!(error, UrlServiceError::NotFound(_)) {
+        metrics.redirect_not_found_total.inc();
+    }
+    resolve_error_response(error)
+}
+
+/// Unknown, expired and deleted codes carry distinct `code` fields, so clients can tell
+/// churned links from bad guesses.
+fn resolve_error_response(error: UrlServiceError) -> HttpResponse {
+    match error {
+        UrlServiceError::NotFound(_) => {
+            HttpResponse::NotFound().json(ErrorResponse::with_code("URL not found", "not_found"))
         }
-        Err(UrlServiceError::EncryptionError(e)) => {
+        UrlServiceError::Expired(_) => {
+            HttpResponse::Gone().json(ErrorResponse::with_code("URL has expired", "expired"))
+        }
+        UrlServiceError::Deleted(_) => {
+            HttpResponse::Gone().json(ErrorResponse::with_code("URL has been deleted", "deleted"))
+        }
+        UrlServiceError::EncryptionError(e) => {
             error!("Encryption error while retrieving URL: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to process URL data"
-            }))
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to process URL data"))
         }
-        Err(e) => {
+        e => {
             error!("Failed to retrieve URL: {}", e);
-            HttpResponse::InternalServerError().json(serde_json::json!({
-                "error": "Failed to retrieve URL"
-            }))
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to retrieve URL"))
         }
     }
-} 
\ No newline at end of file
+}
+
+/// Resolves a short link to its destination as JSON instead of redirecting, without counting a click.
+#[utoipa::path(
+    get,
+    path = "/api/v1/expand/{short_code}",
+    tag = "Links",
+    params(("short_code" = String, Path, description = "Short code or custom alias")),
+    responses(
+        (status = 200, description = "Destination", body = ExpandResponse),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 410, description = "Link expired or was deleted", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn expand_url(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    debug!(short_code = %short_code, "Expanding short URL");
+
+    // Expanding is a lookup, so one-time links are left for the redirect to consume
+    match service.peek_url(&short_code).await {
+        Ok(original_url) => HttpResponse::Ok().json(ExpandResponse {
+            short_code,
+            original_url,
+        }),
+        Err(e) => resolve_error_response(e),
+    }
+}
+
+/// Resolves many short codes at once without counting clicks or consuming one-time links.
+/// Unknown or expired codes are reported with `found: false` rather than failing the whole request.
+#[utoipa::path(
+    post,
+    path = "/api/v1/expand/batch",
+    tag = "Links",
+    request_body = BatchExpandRequest,
+    responses(
+        (status = 200, description = "One result per code, in request order", body = [BatchExpandItem]),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn expand_batch(
+    service: web::Data<UrlService>,
+    config: web::Data<Config>,
+    req: web::Json<BatchExpandRequest>,
+) -> impl Responder {
+    let codes = req.into_inner().codes;
+    // Bounds the lookups one call can trigger
+    if codes.len() > config.server.max_batch_size {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("At most {} codes can be expanded per request", config.server.max_batch_size)));
+    }
+    debug!("Expanding batch of {} short URLs", codes.len());
+
+    let mut items = Vec::with_capacity(codes.len());
+    for code in codes {
+        let original_url = match service.peek_url(&code).await {
+            Ok(url) => Some(url),
+            Err(UrlServiceError::NotFound(_) | UrlServiceError::Expired(_) | UrlServiceError::Deleted(_)) => None,
+            Err(e) => return resolve_error_response(e),
+        };
+        items.push(BatchExpandItem {
+            found: original_url.is_some(),
+            code,
+            original_url,
+        });
+    }
+
+    HttpResponse::Ok().json(items)
+}
+
+/// Checks whether a custom alias is free.
+#[utoipa::path(
+    get,
+    path = "/api/v1/alias/{alias}/available",
+    tag = "Links",
+    params(("alias" = String, Path, description = "Alias to check")),
+    responses(
+        (status = 200, description = "Availability", body = AliasAvailability),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn alias_available(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let alias = path.into_inner();
+    debug!(alias = %alias, "Checking alias availability");
+
+    match service.is_alias_available(&alias).await {
+        Ok(available) => HttpResponse::Ok().json(AliasAvailability { available }),
+        Err(UrlServiceError::InvalidAlias(msg)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new(format!("Invalid alias: {}", msg)))
+        }
+        Err(e) => {
+            error!("Failed to check alias availability: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to check alias availability"))
+        }
+    }
+}
+
+/// Click statistics for a link.
+#[utoipa::path(
+    get,
+    path = "/api/v1/{short_code}/stats",
+    tag = "Stats",
+    params(("short_code" = String, Path, description = "Short code or custom alias")),
+    responses(
+        (status = 200, description = "Statistics", body = UrlStatsResponse),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn url_stats(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    debug!(short_code = %short_code, "Fetching stats for short URL");
+
+    match service.get_stats(&short_code).await {
+        Ok(stats) => HttpResponse::Ok().json(UrlStatsResponse {
+            short_code: stats.short_code,
+            original_url: stats.original_url,
+            clicks: stats.clicks,
+            created_at: stats.created_at,
+            expires_at: stats.expires_at,
+            last_accessed: stats.last_accessed,
+            description: stats.description,
+            tags: stats.tags,
+            unique_visitors: stats.unique_visitors,
+            referrers: stats.referrers,
+            user_agents: stats.user_agents,
+        }),
+        Err(UrlServiceError::NotFound(_)) => {
+            HttpResponse::NotFound().json(ErrorResponse::new("URL not found"))
+        }
+        Err(e) => {
+            error!("Failed to fetch URL stats: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to fetch URL stats"))
+        }
+    }
+}
+
+/// Clicks per UTC day for a link, oldest first.
+#[utoipa::path(
+    get,
+    path = "/api/v1/{short_code}/stats/daily",
+    tag = "Stats",
+    params(("short_code" = String, Path, description = "Short code or custom alias"), DailyQuery),
+    responses(
+        (status = 200, description = "One entry per day, oldest first", body = [DailyClicksItem]),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn daily_stats(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+    query: web::Query<DailyQuery>,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    let days = query.days.unwrap_or(DEFAULT_DAILY_DAYS);
+    debug!(short_code = %short_code, days, "Fetching daily clicks for short URL");
+
+    match service.daily_clicks(&short_code, days).await {
+        Ok(daily) => HttpResponse::Ok().json(
+            daily
+                .into_iter()
+                .map(|day| DailyClicksItem { date: day.date, count: day.count })
+                .collect::<Vec<_>>(),
+        ),
+        Err(UrlServiceError::NotFound(_)) => {
+            HttpResponse::NotFound().json(ErrorResponse::new("URL not found"))
+        }
+        Err(e) => {
+            error!("Failed to fetch daily clicks: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to fetch daily clicks"))
+        }
+    }
+}
+
+fn manage_token(req: &HttpRequest) -> Option<&str> {
+    header_str(req, MANAGE_TOKEN_HEADER)
+}
+
+fn header_str(req: &HttpRequest, name: impl header::AsHeaderName) -> Option<&str> {
+    req.headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+}
+
+/// Changes a link's destination.
+#[utoipa::path(
+    put,
+    path = "/api/v1/{short_code}",
+    tag = "Links",
+    params(("short_code" = String, Path, description = "Short code or custom alias")),
+    request_body = UpdateRequest,
+    security(("ApiKey" = [], "ManageToken" = [])),
+    responses(
+        (status = 200, description = "Updated link", body = ShortenResponse),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 403, description = "Missing or invalid management token", body = ErrorResponse),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 410, description = "Link expired or was deleted", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn update_url(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+    req: HttpRequest,
+    body: web::Json<UpdateRequest>,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    debug!(short_code = %short_code, "Updating destination of short URL");
+
+    match service.update_url(&short_code, body.into_inner().url, manage_token(&req)).await {
+        Ok(updated) => HttpResponse::Ok().json(ShortenResponse::from(updated)),
+        Err(e @ (UrlServiceError::NotFound(_) | UrlServiceError::Expired(_) | UrlServiceError::Deleted(_))) => {
+            resolve_error_response(e)
+        }
+        Err(UrlServiceError::InvalidUrl(msg)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new(msg))
+        }
+        Err(UrlServiceError::SelfReference(_)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new("URL points back at this shortener"))
+        }
+        Err(UrlServiceError::DomainNotAllowed(host)) => {
+            HttpResponse::Forbidden().json(ErrorResponse::new(format!("Domain '{}' is not allowed", host)))
+        }
+        Err(UrlServiceError::InvalidManageToken(_)) => {
+            HttpResponse::Forbidden().json(ErrorResponse::new("Missing or invalid management token"))
+        }
+        Err(e) => {
+            error!("Failed to update URL: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to update URL"))
+        }
+    }
+}
+
+/// Deletes a link; it can be restored until the undo window passes.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/{short_code}",
+    tag = "Links",
+    params(("short_code" = String, Path, description = "Short code or custom alias")),
+    security(("ApiKey" = [], "ManageToken" = [])),
+    responses(
+        (status = 204, description = "Deleted"),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 403, description = "Missing or invalid management token", body = ErrorResponse),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn delete_url(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    debug!(short_code = %short_code, "Deleting short URL");
+
+    match service.delete_url(&short_code, manage_token(&req)).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(UrlServiceError::NotFound(_)) => {
+            HttpResponse::NotFound().json(ErrorResponse::new("URL not found"))
+        }
+        Err(UrlServiceError::InvalidManageToken(_)) => {
+            HttpResponse::Forbidden().json(ErrorResponse::new("Missing or invalid management token"))
+        }
+        Err(e) => {
+            error!("Failed to delete URL: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to delete URL"))
+        }
+    }
+}
+
+/// Undoes a delete.
+#[utoipa::path(
+    post,
+    path = "/api/v1/{short_code}/restore",
+    tag = "Links",
+    params(("short_code" = String, Path, description = "Short code or custom alias")),
+    security(("ApiKey" = [], "ManageToken" = [])),
+    responses(
+        (status = 204, description = "Restored"),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 403, description = "Missing or invalid management token", body = ErrorResponse),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 409, description = "Link is not deleted", body = ErrorResponse),
+        (status = 410, description = "Undo window has passed", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn restore_url(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+    req: HttpRequest,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    debug!(short_code = %short_code, "Restoring deleted short URL");
+
+    match service.restore_url(&short_code, manage_token(&req)).await {
+        Ok(()) => HttpResponse::NoContent().finish(),
+        Err(UrlServiceError::NotFound(_)) => {
+            HttpResponse::NotFound().json(ErrorResponse::new("URL not found"))
+        }
+        Err(UrlServiceError::NotDeleted(_)) => {
+            HttpResponse::Conflict().json(ErrorResponse::new("URL is not deleted"))
+        }
+        Err(UrlServiceError::Expired(_)) => {
+            HttpResponse::Gone().json(ErrorResponse::new("Undo window has passed"))
+        }
+        Err(UrlServiceError::InvalidManageToken(_)) => {
+            HttpResponse::Forbidden().json(ErrorResponse::new("Missing or invalid management token"))
+        }
+        Err(e) => {
+            error!("Failed to restore URL: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to restore URL"))
+        }
+    }
+}
+
+/// QR code for a short link.
+#[utoipa::path(
+    get,
+    path = "/api/v1/{short_code}/qr",
+    tag = "Links",
+    params(("short_code" = String, Path, description = "Short code or custom alias"), QrQuery),
+    responses(
+        (status = 200, description = "QR code image", content(("image/png" = [u8]), ("image/svg+xml" = String))),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 404, description = "Short code does not exist", body = ErrorResponse),
+        (status = 410, description = "Link expired or was deleted", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn qr_code(
+    service: web::Data<UrlService>,
+    path: web::Path<String>,
+    query: web::Query<QrQuery>,
+) -> impl Responder {
+    let short_code = path.into_inner();
+    let format = match query.format.as_deref() {
+        None => QrFormat::Png,
+        Some(value) => match QrFormat::parse(value) {
+            Some(format) => format,
+            None => {
+                return HttpResponse::BadRequest().json(ErrorResponse::new("Unsupported QR format, expected 'png' or 'svg'"));
+            }
+        },
+    };
+    debug!(short_code = %short_code, format = ?format, "Generating QR code");
+
+    if let Err(e) = service.ensure_active(&short_code).await {
+        return resolve_error_response(e);
+    }
+
+    match render_qr(&service.full_short_url(&short_code), format) {
+        Ok(bytes) => HttpResponse::Ok()
+            .content_type(format.content_type())
+            .body(bytes),
+        Err(e) => {
+            error!("Failed to render QR code: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to generate QR code"))
+        }
+    }
+}
+
+/// Lists live links page by page.
+#[utoipa::path(
+    get,
+    path = "/api/v1/urls",
+    tag = "Stats",
+    params(ListQuery),
+    security(("ApiKey" = [])),
+    responses(
+        (status = 200, description = "A page of links", body = UrlListResponse),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn list_urls(
+    service: web::Data<UrlService>,
+    query: web::Query<ListQuery>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(MAX_PAGE_SIZE).min(MAX_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0);
+    debug!("Listing URLs with limit {} and offset {}", limit, offset);
+
+    let page = match query.tag.as_deref() {
+        Some(tag) => service.list_tagged_urls(tag, limit, offset).await,
+        None => service.list_urls(limit, offset).await,
+    };
+    match page {
+        Ok(page) => HttpResponse::Ok().json(UrlListResponse {
+            total: page.total,
+            limit,
+            offset,
+            urls: page.urls.into_iter().map(UrlListItem::from).collect(),
+        }),
+        Err(UrlServiceError::InvalidTags(msg)) => {
+            HttpResponse::BadRequest().json(ErrorResponse::new(msg))
+        }
+        Err(e) => {
+            error!("Failed to list URLs: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to list URLs"))
+        }
+    }
+}
+
+/// Most-clicked live links.
+#[utoipa::path(
+    get,
+    path = "/api/v1/top",
+    tag = "Stats",
+    params(TopQuery),
+    security(("ApiKey" = [])),
+    responses(
+        (status = 200, description = "Links, most clicked first", body = TopUrlsResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn top_urls(
+    service: web::Data<UrlService>,
+    query: web::Query<TopQuery>,
+) -> impl Responder {
+    let limit = query.limit.unwrap_or(DEFAULT_TOP_LIMIT).min(MAX_TOP_LIMIT);
+    debug!("Listing top {} URLs by clicks", limit);
+
+    match service.top_urls(limit).await {
+        Ok(urls) => HttpResponse::Ok().json(TopUrlsResponse {
+            limit,
+            urls: urls.into_iter().map(UrlListItem::from).collect(),
+        }),
+        Err(e) => {
+            error!("Failed to list top URLs: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to list top URLs"))
+        }
+    }
+}
+
+/// Number of stored links and storage size.
+#[utoipa::path(
+    get,
+    path = "/api/v1/count",
+    tag = "Stats",
+    responses(
+        (status = 200, description = "Counts", body = CountResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn count_urls(service: web::Data<UrlService>) -> impl Responder {
+    let counted = match service.count_urls().await {
+        Ok(total) => service.storage_size().await.map(|size| (total, size)),
+        Err(e) => Err(e),
+    };
+    match counted {
+        Ok((total, size_on_disk)) => HttpResponse::Ok().json(CountResponse { total, size_on_disk }),
+        Err(e) => {
+            error!("Failed to count URLs: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to count URLs"))
+        }
+    }
+}
+
+/// Reclaims disk space.
+///
+/// Admin operation; like other writes under the API scope it requires an API key when keys are configured.
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/compact",
+    tag = "Admin",
+    security(("ApiKey" = [])),
+    responses(
+        (status = 200, description = "Sizes in bytes before and after compaction", body = CompactResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn compact_storage(service: web::Data<UrlService>) -> impl Responder {
+    info!("Compacting storage...");
+    match service.compact_storage().await {
+        Ok((size_before, size_after)) => {
+            info!(size_before, size_after, "Storage compaction finished");
+            HttpResponse::Ok().json(CompactResponse { size_before, size_after })
+        }
+        Err(e) => {
+            error!("Failed to compact storage: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to compact storage"))
+        }
+    }
+}
+
+/// Exports every live link.
+///
+/// Streams every mapping as a JSON array, decrypting one entry at a time so
+/// large databases never have to be held in memory.
+#[utoipa::path(
+    get,
+    path = "/api/v1/export",
+    tag = "Admin",
+    security(("ApiKey" = [])),
+    responses(
+        (status = 200, description = "All links as a streamed JSON array", body = [UrlListItem]),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn export_urls(service: web::Data<UrlService>) -> impl Responder {
+    let entries = match service.stream_urls().await {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Failed to start export: {}", e);
+            return HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to export URLs"));
+        }
+    };
+
+    let service = service.into_inner();
+    let items = entries
+        .then(move |entry| {
+            let service = Arc::clone(&service);
+            async move {
+                match entry {
+                    Ok(entry) => service.summarize(entry).await,
+                    Err(e) => Err(e.into()),
+                }
+            }
+        })
+        .filter_map(|item| async move { item.transpose() })
+        .enumerate()
+        .map(|(index, item)| {
+            let summary = item.map_err(|e| {
+                error!("Export aborted: {}", e);
+                actix_web::error::ErrorInternalServerError(e.to_string())
+            })?;
+            let mut chunk = if index == 0 { Vec::new() } else { b",".to_vec() };
+            serde_json::to_writer(&mut chunk, &UrlListItem::from(summary))
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            Ok::<_, actix_web::Error>(Bytes::from(chunk))
+        });
+
+    let body = stream::once(async { Ok(Bytes::from_static(b"[")) })
+        .chain(items)
+        .chain(stream::once(async { Ok(Bytes::from_static(b"]")) }));
+
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .streaming(body)
+}
+
+/// Imports links from a backup of at most `MAX_BATCH_SIZE` entries.
+#[utoipa::path(
+    post,
+    path = "/api/v1/import",
+    tag = "Admin",
+    params(ImportQuery),
+    request_body = [ImportItem],
+    security(("ApiKey" = [])),
+    responses(
+        (status = 200, description = "Import summary", body = ImportSummary),
+        (status = 400, description = "Invalid input", body = ErrorResponse),
+        (status = 401, description = "Missing or invalid API key", body = ErrorResponse),
+        (status = 500, description = "Unexpected server error", body = ErrorResponse),
+    ),
+)]
+pub async fn import_urls(
+    service: web::Data<UrlService>,
+    config: web::Data<Config>,
+    query: web::Query<ImportQuery>,
+    items: web::Json<Vec<ImportItem>>,
+) -> impl Responder {
+    if items.len() > config.server.max_batch_size {
+        return HttpResponse::BadRequest().json(ErrorResponse::new(format!("At most {} entries can be imported per request", config.server.max_batch_size)));
+    }
+    let entries: Vec<ImportEntry> = items
+        .into_inner()
+        .into_iter()
+        .map(|item| ImportEntry {
+            short_code: item.short_code,
+            original_url: item.original_url,
+            expires_at: item.expires_at,
+            created_at: item.created_at,
+            one_time: item.one_time,
+            max_clicks: item.max_clicks,
+            clicks: item.clicks,
+            description: item.description,
+            tags: item.tags,
+        })
+        .collect();
+    debug!("Importing {} URLs", entries.len());
+
+    let overwrite = query.mode == ImportMode::Overwrite;
+    match service.import_urls(entries, overwrite).await {
+        Ok(results) => {
+            let mut summary = ImportSummary::default();
+            for result in results {
+                match result {
+                    Ok(ImportOutcome::Created) => summary.created += 1,
+                    Ok(ImportOutcome::Skipped) => summary.skipped += 1,
+                    Err(e) => {
+                        warn!("Failed to import URL: {}", e);
+                        summary.failed += 1;
+                    }
+                }
+            }
+            HttpResponse::Ok().json(summary)
+        }
+        Err(e) => {
+            error!("Failed to persist URL import: {}", e);
+            HttpResponse::InternalServerError().json(ErrorResponse::new("Failed to import URLs"))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use crate::repositories::memory_repository::InMemoryUrlRepository;
+    use crate::repositories::url_repository::UrlRepository;
+    use crate::services::test_support::{service_with, test_config, test_service};
+
+    /// The public read routes, wired like `main` does.
+    macro_rules! test_app {
+        ($service:expr) => {
+            test_app!($service, test_config())
+        };
+        ($service:expr, $config:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($service))
+                    .app_data(web::Data::new($config))
+                    .app_data(web::Data::new(Metrics::new(prometheus::Registry::new()).unwrap()))
+                    .route("/api/v1/expand/batch", web::post().to(expand_batch))
+                    .route("/api/v1/expand/{short_code}", web::get().to(expand_url))
+                    .route("/api/v1/{short_code}", web::put().to(update_url))
+                    .route("/api/v1/{short_code}/qr", web::get().to(qr_code))
+                    .route("/{short_url}{tail:.*}", web::get().to(redirect)),
+            )
+            .await
+        };
+    }
+
+    async fn one_time_link(service: &UrlService) -> String {
+        let options = ShortenOptions { one_time: true, ..Default::default() };
+        service
+            .shorten_url("https://example.com/secret".to_string(), options, None)
+            .await
+            .unwrap()
+            .short_code
+    }
+
+    #[actix_web::test]
+    async fn expanding_does_not_consume_one_time_links() {
+        let service = test_service();
+        let code = one_time_link(&service).await;
+        let app = test_app!(service);
+
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri(&format!("/api/v1/expand/{}", code)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+        let req = test::TestRequest::get().uri(&format!("/{}", code)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 302);
+        let req = test::TestRequest::get().uri(&format!("/{}", code)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 410);
+    }
+
+    #[actix_web::test]
+    async fn cached_redirects_respect_link_limits() {
+        let mut config = test_config();
+        config.shortener.redirect_status = 301;
+        config.shortener.redirect_cache_secs = 3600;
+        let repository = Arc::new(InMemoryUrlRepository::new());
+        let service = service_with(repository.clone(), config.clone());
+        let shorten = |url: &str, options: ShortenOptions| {
+            let shortened = service.shorten_url(url.to_string(), options, None);
+            async move { shortened.await.unwrap().short_code }
+        };
+        let permanent = shorten("https://example.com/permanent", ShortenOptions::default()).await;
+        let one_time = one_time_link(&service).await;
+        let limited = shorten("https://example.com/limited", ShortenOptions { max_clicks: Some(5), ..Default::default() }).await;
+        let expiring = shorten("https://example.com/expiring", ShortenOptions { expires_in_seconds: Some(60), ..Default::default() }).await;
+        let app = test_app!(service, config);
+        let get = |code: &str, etag: Option<&str>| {
+            let mut req = test::TestRequest::get().uri(&format!("/{}", code));
+            if let Some(etag) = etag {
+                req = req.insert_header((header::IF_NONE_MATCH, etag));
+            }
+            req.to_request()
+        };
+
+        let resp = test::call_service(&app, get(&permanent, None)).await;
+        assert_eq!(resp.status(), 301);
+        assert_eq!(resp.headers().get(header::CACHE_CONTROL).unwrap(), "public, max-age=3600");
+        let etag = resp.headers().get(header::ETAG).unwrap().to_str().unwrap().to_string();
+        // Revalidating is answered without counting another click
+        let resp = test::call_service(&app, get(&permanent, Some(&etag))).await;
+        assert_eq!(resp.status(), 304);
+        assert_eq!(repository.get_clicks(&permanent).await.unwrap(), 1);
+
+        for code in [&one_time, &limited] {
+            let resp = test::call_service(&app, get(code, Some("*"))).await;
+            assert_eq!(resp.status(), 301);
+            assert_eq!(resp.headers().get(header::CACHE_CONTROL).unwrap(), "no-cache");
+            assert!(resp.headers().get(header::ETAG).is_none());
+        }
+        assert_eq!(test::call_service(&app, get(&one_time, None)).await.status(), 410);
+
+        let resp = test::call_service(&app, get(&expiring, None)).await;
+        let cache_control = resp.headers().get(header::CACHE_CONTROL).unwrap().to_str().unwrap();
+        let max_age: u64 = cache_control.strip_prefix("public, max-age=").unwrap().parse().unwrap();
+        assert!((59..=60).contains(&max_age), "{}", cache_control);
+    }
+
+    #[actix_web::test]
+    async fn forwarded_addresses_are_only_trusted_from_configured_proxies() {
+        let mut config = test_config();
+        config.server.trusted_proxies = vec!["10.0.0.1".parse().unwrap()];
+        let request = |peer: &str| {
+            test::TestRequest::default()
+                .peer_addr(peer.parse().unwrap())
+                .insert_header(("X-Forwarded-For", "198.51.100.7"))
+                .to_http_request()
+        };
+
+        assert_eq!(client_ip(&request("10.0.0.1:4000"), &config).as_deref(), Some("198.51.100.7"));
+        assert_eq!(client_ip(&request("203.0.113.5:4000"), &config).as_deref(), Some("203.0.113.5"));
+    }
+
+    #[actix_web::test]
+    async fn oversized_bodies_are_rejected_before_parsing() {
+        let config = test_config();
+        let limit = config.server.max_body_bytes;
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_service()))
+                .app_data(web::Data::new(config))
+                .app_data(web::Data::new(Metrics::new(prometheus::Registry::new()).unwrap()))
+                .app_data(web::PayloadConfig::new(limit))
+                .app_data(web::JsonConfig::default().limit(limit))
+                .app_data(web::FormConfig::default().limit(limit))
+                .route("/api/v1/shorten", web::post().to(shorten_url)),
+        )
+        .await;
+
+        let url = format!("https://example.com/{}", "a".repeat(limit));
+        let req = test::TestRequest::post()
+            .uri("/api/v1/shorten")
+            .set_json(serde_json::json!({ "url": url }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 413);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/shorten")
+            .set_json(serde_json::json!({ "url": "https://example.com/" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 201);
+    }
+
+    #[actix_web::test]
+    async fn lookups_report_why_a_link_is_unavailable() {
+        let service = test_service();
+        let deleted = service.shorten_url("https://example.com/deleted".to_string(), ShortenOptions::default(), None).await.unwrap();
+        service.delete_url(&deleted.short_code, deleted.manage_token.as_deref()).await.unwrap();
+        let expired = ImportEntry {
+            short_code: "expired".to_string(),
+            original_url: "https://example.com/expired".to_string(),
+            expires_at: Some(1),
+            created_at: None,
+            one_time: false,
+            max_clicks: None,
+            clicks: 0,
+            description: None,
+            tags: Vec::new(),
+        };
+        service.import_urls(vec![expired], false).await.unwrap().remove(0).unwrap();
+        let app = test_app!(service);
+
+        let cases = [("missing", 404, "not_found"), (deleted.short_code.as_str(), 410, "deleted"), ("expired", 410, "expired")];
+        for (code, status, reason) in cases {
+            let requests = [
+                test::TestRequest::get().uri(&format!("/{}", code)),
+                test::TestRequest::get().uri(&format!("/api/v1/expand/{}", code)),
+                test::TestRequest::get().uri(&format!("/api/v1/{}/qr", code)),
+                test::TestRequest::put()
+                    .uri(&format!("/api/v1/{}", code))
+                    .set_json(serde_json::json!({ "url": "https://example.com/new" })),
+            ];
+            for req in requests {
+                let response = test::call_service(&app, req.to_request()).await;
+                assert_eq!(response.status(), status, "{}", code);
+                let body: serde_json::Value = test::read_body_json(response).await;
+                assert_eq!(body["code"], reason, "{}", code);
+            }
+        }
+    }
+
+    #[actix_web::test]
+    async fn import_body_limit_follows_the_batch_size() {
+        let mut config = test_config();
+        config.server.max_batch_size = 2;
+        let limit = import_body_limit(&config);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(test_service()))
+                .app_data(web::Data::new(config.clone()))
+                .service(
+                    web::resource("/api/v1/import")
+                        .app_data(web::JsonConfig::default().limit(limit))
+                        .route(web::post().to(import_urls)),
+                ),
+        )
+        .await;
+
+        // A full batch with every field at its limit fits
+        let entry = |code: &str| {
+            serde_json::json!({
+                "short_code": code,
+                "original_url": format!("https://example.com/{}", "a".repeat(config.shortener.max_url_length - 20)),
+                "description": "\u{1}".repeat(MAX_DESCRIPTION_LENGTH),
+                "tags": vec!["\u{1}".repeat(MAX_TAG_LENGTH); MAX_TAGS],
+            })
+        };
+        let req = test::TestRequest::post()
+            .uri("/api/v1/import")
+            .set_json(serde_json::json!([entry("first"), entry("second")]))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let req = test::TestRequest::post()
+            .uri("/api/v1/import")
+            .set_json(serde_json::json!([{ "short_code": "big", "original_url": "a".repeat(limit) }]))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 413);
+    }
+
+    #[actix_web::test]
+    async fn batch_expansion_does_not_consume_one_time_links() {
+        let service = test_service();
+        let code = one_time_link(&service).await;
+        let app = test_app!(service);
+
+        for _ in 0..2 {
+            let req = test::TestRequest::post()
+                .uri("/api/v1/expand/batch")
+                .set_json(serde_json::json!({ "codes": [code, "missing"] }))
+                .to_request();
+            let items: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(items[0]["found"], true);
+            assert_eq!(items[0]["original_url"], "https://example.com/secret");
+            assert_eq!(items[1]["found"], false);
+        }
+        let req = test::TestRequest::get().uri(&format!("/{}", code)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 302);
+    }
+
+    #[actix_web::test]
+    async fn previews_do_not_consume_one_time_links() {
+        let service = test_service();
+        let code = one_time_link(&service).await;
+        let app = test_app!(service);
+
+        for _ in 0..2 {
+            let req = test::TestRequest::get().uri(&format!("/{}?preview", code)).to_request();
+            let body = test::call_and_read_body(&app, req).await;
+            assert!(String::from_utf8_lossy(&body).contains("https://example.com/secret"));
+        }
+        let req = test::TestRequest::get().uri(&format!("/{}", code)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 302);
+    }
+}
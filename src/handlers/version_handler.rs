@@ -0,0 +1,28 @@
+use actix_web::{HttpResponse, Responder};
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Serialize, ToSchema)]
+pub struct VersionResponse {
+    version: &'static str,
+    git_sha: &'static str,
+    /// Unix milliseconds
+    build_time: Option<u64>,
+}
+
+/// Identifies the running build; values are fixed at compile time by `build.rs`.
+#[utoipa::path(
+    get,
+    path = "/api/v1/version",
+    tag = "Service",
+    responses(
+        (status = 200, description = "Build information", body = VersionResponse),
+    ),
+)]
+pub async fn version() -> impl Responder {
+    HttpResponse::Ok().json(VersionResponse {
+        version: env!("CARGO_PKG_VERSION"),
+        git_sha: env!("GIT_SHA"),
+        build_time: env!("BUILD_TIME").parse::<u64>().ok(),
+    })
+}
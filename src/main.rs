@@ -1,7 +1,9 @@
-use actix_web::{web, App, HttpServer, middleware::Logger};
+use actix_web::{guard, http::KeepAlive, rt, rt::task::JoinHandle, web, App, HttpServer, middleware::{Compress, Condition, Logger}};
 use actix_cors::Cors;
+use rustls::pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
 use std::sync::Arc;
-use tracing::{info, debug, error};
+use std::time::Duration;
+use tracing::{info, debug, error, warn};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
 use std::error::Error as StdError;
 use std::fmt;
@@ -10,11 +12,25 @@ mod handlers;
 mod config;
 mod services;
 mod repositories;
+mod metrics;
+mod middleware;
 
-use config::Config;
-use handlers::url_handler::{shorten_url, redirect, health_check};
-use repositories::url_repository::SledUrlRepository;
+use config::{Config, LogFormat, ServerConfig, StorageBackend, StorageMode};
+use handlers::url_handler::{liveness, shorten_url, shorten_batch, redirect, health_check, delete_url, restore_url, update_url, expand_url, expand_batch, url_stats, alias_available, qr_code, list_urls, top_urls, daily_stats, count_urls, compact_storage, export_urls, import_urls, import_body_limit};
+use handlers::metrics_handler::metrics as metrics_endpoint;
+use handlers::version_handler::version;
+use handlers::frontend_handler::{accepts_html, index};
+use handlers::openapi_handler::openapi;
+use handlers::site_handler::{favicon, robots, well_known, Favicon};
+use metrics::Metrics;
+use middleware::url_validator::UrlValidator;
+use middleware::api_key::ApiKeyAuth;
+use middleware::security_headers::SecurityHeaders;
+use middleware::request_id::{RequestId, REQUEST_ID_HEADER};
+use repositories::memory_repository::InMemoryUrlRepository;
+use repositories::url_repository::{SledUrlRepository, UrlRepository};
 use services::url_service::{UrlService, UrlServiceError};
+use services::encryption_service::EncryptionService;
 
 #[derive(Debug)]
 enum AppError {
@@ -22,6 +38,7 @@ enum AppError {
     Storage(sled::Error),
     Service(UrlServiceError),
     Server(std::io::Error),
+    Metrics(prometheus::Error),
 }
 
 impl fmt::Display for AppError {
@@ -31,6 +48,7 @@ impl fmt::Display for AppError {
             Self::Storage(e) => write!(f, "Storage error: {}", e),
             Self::Service(e) => write!(f, "Service error: {}", e),
             Self::Server(e) => write!(f, "Server error: {}", e),
+            Self::Metrics(e) => write!(f, "Metrics error: {}", e),
         }
     }
 }
@@ -42,6 +60,7 @@ impl StdError for AppError {
             Self::Storage(e) => Some(e),
             Self::Service(e) => Some(e),
             Self::Server(e) => Some(e),
+            Self::Metrics(e) => Some(e),
         }
     }
 }
@@ -70,32 +89,158 @@ impl From<std::io::Error> for AppError {
     }
 }
 
+impl From<prometheus::Error> for AppError {
+    fn from(error: prometheus::Error) -> Self {
+        Self::Metrics(error)
+    }
+}
+
+/// Periodically purges expired links and old daily click buckets for as long as the server runs.
+fn spawn_cleanup(service: web::Data<UrlService>, interval_secs: u64) -> JoinHandle<()> {
+    rt::spawn(async move {
+        let mut interval = rt::time::interval(Duration::from_secs(interval_secs));
+        loop {
+            interval.tick().await;
+            match service.purge_expired().await {
+                Ok(0) => {}
+                Ok(count) => info!("Purged {} expired URL mappings", count),
+                Err(e) => error!("Failed to purge expired URLs: {}", e),
+            }
+            match service.purge_daily_clicks().await {
+                Ok(0) => {}
+                Ok(count) => info!("Purged {} daily click buckets past retention", count),
+                Err(e) => error!("Failed to purge daily clicks: {}", e),
+            }
+        }
+    })
+}
+
+/// Removes a socket file left behind by a previous run, which would otherwise make binding
+/// fail. Anything at the path that isn't a socket is left alone.
+#[cfg(unix)]
+fn remove_stale_socket(path: &str) -> std::io::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path),
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Reads the PEM certificate chain and private key for HTTPS, failing with a message that
+/// names the file that couldn't be used.
+fn load_tls_config(cert_path: &str, key_path: &str) -> Result<rustls::ServerConfig, AppError> {
+    let certs = CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| AppError::Config(format!("TLS_CERT_PATH could not be loaded: {}", e).into()))?;
+    if certs.is_empty() {
+        return Err(AppError::Config(format!("TLS_CERT_PATH contains no certificates: {}", cert_path).into()));
+    }
+    let key = PrivateKeyDer::from_pem_file(key_path)
+        .map_err(|e| AppError::Config(format!("TLS_KEY_PATH could not be loaded: {}", e).into()))?;
+
+    rustls::ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+        .map_err(|e| AppError::Config(format!("TLS certificate and key are not usable: {}", e).into()))
+}
+
+fn build_cors(server: &ServerConfig) -> Cors {
+    let mut cors = Cors::default()
+        .max_age(3600)
+        .expose_headers([REQUEST_ID_HEADER]);
+
+    if server.allowed_origins.iter().any(|origin| origin == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        for origin in &server.allowed_origins {
+            cors = cors.allowed_origin(origin);
+        }
+    }
+
+    if server.allowed_methods.iter().any(|method| method == "*") {
+        cors = cors.allow_any_method();
+    } else {
+        cors = cors.allowed_methods(server.allowed_methods.iter().map(String::as_str));
+    }
+
+    if server.allowed_headers.iter().any(|header| header == "*") {
+        cors = cors.allow_any_header();
+    } else {
+        cors = cors.allowed_headers(server.allowed_headers.iter().map(String::as_str));
+    }
+
+    cors
+}
+
 #[actix_web::main]
 async fn main() -> std::result::Result<(), Box<dyn StdError>> {
+    // A `.env` file fills in variables that aren't already set in the real environment
+    if let Err(e) = dotenvy::dotenv() {
+        if !e.not_found() {
+            return Err(AppError::Config(format!("Failed to load .env file: {}", e).into()).into());
+        }
+    }
+
     // Initialize configuration first
-    let config = Arc::new(Config::new().map_err(AppError::Config)?);
+    let config = Config::load().map_err(AppError::Config)?;
+    config.validate().map_err(AppError::Config)?;
+    let config = Arc::new(config);
     
     // Initialize tracing with config
     tracing_subscriber::registry()
         .with(tracing_subscriber::EnvFilter::try_from_default_env()
             .unwrap_or_else(|_| EnvFilter::new(&config.logging.level)))
-        .with(tracing_subscriber::fmt::layer())
+        .with((config.logging.format == LogFormat::Pretty).then(tracing_subscriber::fmt::layer))
+        .with((config.logging.format == LogFormat::Json).then(|| tracing_subscriber::fmt::layer().json()))
         .init();
 
+    // `rotate-key` generates a new encryption key version and exits
+    if std::env::args().nth(1).as_deref() == Some("rotate-key") {
+        let encryption = EncryptionService::new(&config.security)
+            .map_err(|e| AppError::Service(UrlServiceError::EncryptionError(e)))?;
+        let version = encryption.rotate_key()
+            .map_err(|e| AppError::Service(UrlServiceError::EncryptionError(e)))?;
+        info!("Rotated encryption key, new version: {}", version);
+        return Ok(());
+    }
+
     info!("Starting application with log level: {}", config.logging.level);
     info!("Initializing storage...");
-    let db = sled::Config::new()
-        .path(&config.storage.path)
-        .mode(sled::Mode::HighThroughput)
-        .flush_every_ms(Some(config.storage.flush_interval_ms))
-        .cache_capacity((1024 * 1024 * config.storage.cache_size_mb) as u64)
-        .open()
-        .map_err(AppError::Storage)?;
-
-    // Initialize repository and service
-    let repository = Arc::new(SledUrlRepository::new(db));
+    let repository: Arc<dyn UrlRepository> = match config.storage.backend {
+        StorageBackend::Sled => {
+            let db = sled::Config::new()
+                .path(&config.storage.path)
+                .mode(match config.storage.mode {
+                    StorageMode::HighThroughput => sled::Mode::HighThroughput,
+                    StorageMode::LowSpace => sled::Mode::LowSpace,
+                })
+                .flush_every_ms(Some(config.storage.flush_interval_ms))
+                .cache_capacity((1024 * 1024 * config.storage.cache_size_mb) as u64)
+                .open()
+                .map_err(AppError::Storage)?;
+            if !config.storage.flush_on_write {
+                warn!(
+                    "Flush on write is disabled, a crash can lose up to {}ms of acknowledged writes",
+                    config.storage.flush_interval_ms
+                );
+            }
+            Arc::new(
+                SledUrlRepository::new(db)
+                    .map_err(AppError::Storage)?
+                    .with_max_retries(config.storage.max_retries),
+            )
+        }
+        StorageBackend::Memory => {
+            warn!("Using in-memory storage, all data will be lost on shutdown");
+            Arc::new(InMemoryUrlRepository::new())
+        }
+    };
+
     debug!("Initializing URL service with encryption...");
-    let service = match UrlService::new(repository, Arc::clone(&config)) {
+    let service = match UrlService::new(Arc::clone(&repository), Arc::clone(&config)) {
         Ok(service) => {
             info!("URL service initialized successfully");
             web::Data::new(service)
@@ -106,33 +251,150 @@ async fn main() -> std::result::Result<(), Box<dyn StdError>> {
         }
     };
     
-    info!("Starting server at {}", config.server_url());
+    match service.backfill_indexes().await {
+        Ok(0) => {}
+        Ok(count) => info!("Indexed {} existing URL mappings", count),
+        Err(e) => error!("Failed to backfill URL index: {}", e),
+    }
+
+    let metrics = web::Data::new(
+        Metrics::new(prometheus::Registry::new()).map_err(AppError::Metrics)?
+    );
+
+    let cleanup = config.cleanup.enabled.then(|| {
+        info!("Purging expired links every {}s", config.cleanup.interval_secs);
+        spawn_cleanup(service.clone(), config.cleanup.interval_secs)
+    });
 
-    HttpServer::new(move || {
+    // Loaded before anything is served, so a bad certificate stops startup
+    let tls = match (&config.server.tls_cert_path, &config.server.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => Some(load_tls_config(cert_path, key_path)?),
+        _ => None,
+    };
+
+    let config_data = web::Data::from(Arc::clone(&config));
+    let favicon_data = web::Data::new(
+        config.server.favicon_path
+            .as_deref()
+            .map(Favicon::load)
+            .transpose()
+            .map_err(|e| AppError::Config(format!("FAVICON_PATH could not be read: {}", e).into()))?,
+    );
+
+    let server = HttpServer::new(move || {
         App::new()
+            .wrap(Condition::new(config_data.server.compression, Compress::default()))
             .wrap(Logger::default())
-            .wrap(
-                Cors::default()
-                    .allowed_origin("http://localhost:3000")
-                    .allowed_methods(vec!["GET", "POST"])
-                    .allowed_headers(vec!["Content-Type"])
-                    .max_age(3600)
-            )
+            .wrap(UrlValidator::new(config_data.shortener.max_url_length))
+            .wrap(build_cors(&config_data.server))
+            .wrap(SecurityHeaders::new(&config_data.headers))
+            // Outermost, so every other middleware and handler logs inside the request span
+            .wrap(RequestId)
             .app_data(service.clone())
-            // Root level redirect for short codes
-            .route("/{short_url}", web::get().to(redirect))
+            .app_data(config_data.clone())
+            .app_data(metrics.clone())
+            .app_data(favicon_data.clone())
+            // Oversized bodies are rejected with 413 before any deserialization
+            .app_data(web::PayloadConfig::new(config_data.server.max_body_bytes))
+            .app_data(web::JsonConfig::default().limit(config_data.server.max_body_bytes))
+            .app_data(web::FormConfig::default().limit(config_data.server.max_body_bytes))
+            .route("/", web::get().guard(guard::fn_guard(accepts_html)).to(index))
+            .route("/robots.txt", web::get().to(robots))
+            .route("/favicon.ico", web::get().to(favicon))
+            .route("/.well-known/{path:.*}", web::get().to(well_known))
+            .route("/metrics", web::get().to(metrics_endpoint))
+            .route("/livez", web::get().to(liveness))
+            .route("/readyz", web::get().to(health_check))
+            // A lookup that uses POST only to carry a body, so it is registered ahead of the
+            // API scope to stay public like other reads
+            .route("/api/v1/expand/batch", web::post().to(expand_batch))
             .service(
                 web::scope("/api/v1")
+                    // Writes need a key; the router has already decoded the path, so encoded
+                    // variants of these routes can't slip past
+                    .wrap(ApiKeyAuth::new(config_data.security.api_keys.clone()))
                     .route("/health", web::get().to(health_check))
+                    .route("/version", web::get().to(version))
+                    .route("/openapi.json", web::get().to(openapi))
                     .route("/shorten", web::post().to(shorten_url))
+                    .route("/shorten/batch", web::post().to(shorten_batch))
+                    // Enumerating reads are guarded per resource, so the check applies to the
+                    // route the request actually reaches however its path was encoded
+                    .service(
+                        web::resource("/urls")
+                            .wrap(ApiKeyAuth::new(config_data.security.api_keys.clone()).with_reads())
+                            .route(web::get().to(list_urls)),
+                    )
+                    .service(
+                        web::resource("/top")
+                            .wrap(ApiKeyAuth::new(config_data.security.api_keys.clone()).with_reads())
+                            .route(web::get().to(top_urls)),
+                    )
+                    .route("/count", web::get().to(count_urls))
+                    .route("/admin/compact", web::post().to(compact_storage))
+                    .service(
+                        web::resource("/export")
+                            .wrap(ApiKeyAuth::new(config_data.security.api_keys.clone()).with_reads())
+                            .route(web::get().to(export_urls)),
+                    )
+                    .service(
+                        web::resource("/import")
+                            .app_data(web::JsonConfig::default().limit(import_body_limit(&config_data)))
+                            .route(web::post().to(import_urls)),
+                    )
+                    .route("/expand/{short_code}", web::get().to(expand_url))
+                    .route("/alias/{alias}/available", web::get().to(alias_available))
+                    .route("/{short_code}", web::put().to(update_url))
+                    .route("/{short_code}", web::delete().to(delete_url))
+                    .route("/{short_code}/restore", web::post().to(restore_url))
+                    .route("/{short_code}/qr", web::get().to(qr_code))
+                    .route("/{short_code}/stats", web::get().to(url_stats))
+                    .route("/{short_code}/stats/daily", web::get().to(daily_stats))
             )
+            // Root level redirect for short codes; registered last since the tail matches any path
+            .route("/{short_url}{tail:.*}", web::get().to(redirect))
+            .route("/{short_url}{tail:.*}", web::head().to(redirect))
     })
-    .bind(config.socket_addr())
-    .map_err(AppError::Server)?
-    .workers(config.server.workers)
-    .run()
-    .await
+    // The backlog only applies to sockets bound after it is set
+    .backlog(config.server.backlog)
+    .keep_alive(match config.server.keep_alive_secs {
+        0 => KeepAlive::Disabled,
+        secs => KeepAlive::Timeout(Duration::from_secs(secs)),
+    });
+
+    let server = match &config.server.unix_socket {
+        #[cfg(unix)]
+        Some(path) => {
+            remove_stale_socket(path).map_err(AppError::Server)?;
+            info!("Starting server on unix socket {}, publishing links under {}", path, config.public_url());
+            server.bind_uds(path)
+        }
+        // Elsewhere `validate` has already rejected a socket path
+        _ => {
+            info!("Starting server at {}://{}", config.scheme(), config.socket_addr());
+            match tls {
+                Some(tls) => server.bind_rustls_0_23(config.socket_addr(), tls),
+                None => server.bind(config.socket_addr()),
+            }
+        }
+    }
     .map_err(AppError::Server)?;
 
+    server
+        .workers(config.server.workers)
+        .shutdown_timeout(config.server.shutdown_timeout_secs)
+        .run()
+        .await
+        .map_err(AppError::Server)?;
+
+    if let Some(cleanup) = cleanup {
+        cleanup.abort();
+    }
+
+    // In-flight requests have drained; make sure nothing buffered is lost
+    info!("Server stopped, flushing storage...");
+    repository.flush().await.map_err(|e| AppError::Service(e.into()))?;
+    info!("Storage flushed, shutdown complete");
+
     Ok(())
 }
@@ -0,0 +1,59 @@
+use prometheus::{Encoder, Histogram, HistogramOpts, IntCounter, IntGauge, Registry, TextEncoder};
+
+pub struct Metrics {
+    registry: Registry,
+    pub shortens_total: IntCounter,
+    pub redirects_total: IntCounter,
+    pub redirect_not_found_total: IntCounter,
+    pub shorten_duration_seconds: Histogram,
+    /// Refreshed on every scrape rather than tracked on each write.
+    pub storage_size_bytes: IntGauge,
+}
+
+impl Metrics {
+    pub fn new(registry: Registry) -> Result<Self, prometheus::Error> {
+        let shortens_total = IntCounter::new(
+            "url_shortens_total",
+            "Total number of URLs shortened",
+        )?;
+        let redirects_total = IntCounter::new(
+            "url_redirects_total",
+            "Total number of successful redirects",
+        )?;
+        let redirect_not_found_total = IntCounter::new(
+            "url_redirect_not_found_total",
+            "Total number of redirects for unknown short codes",
+        )?;
+        let shorten_duration_seconds = Histogram::with_opts(HistogramOpts::new(
+            "url_shorten_duration_seconds",
+            "Latency of shorten requests in seconds",
+        ))?;
+
+        let storage_size_bytes = IntGauge::new(
+            "url_storage_size_bytes",
+            "Size of the URL database on disk in bytes",
+        )?;
+
+        registry.register(Box::new(shortens_total.clone()))?;
+        registry.register(Box::new(redirects_total.clone()))?;
+        registry.register(Box::new(redirect_not_found_total.clone()))?;
+        registry.register(Box::new(shorten_duration_seconds.clone()))?;
+        registry.register(Box::new(storage_size_bytes.clone()))?;
+
+        Ok(Self {
+            registry,
+            shortens_total,
+            redirects_total,
+            redirect_not_found_total,
+            shorten_duration_seconds,
+            storage_size_bytes,
+        })
+    }
+
+    /// Renders all registered metrics in the Prometheus text exposition format.
+    pub fn render(&self) -> Result<Vec<u8>, prometheus::Error> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(buffer)
+    }
+}
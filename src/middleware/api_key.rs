@@ -0,0 +1,167 @@
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::Method,
+    Error, HttpResponse,
+};
+use futures::future::{ready, Ready, LocalBoxFuture};
+use ring::constant_time::verify_slices_are_equal;
+use std::rc::Rc;
+
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+/// Requires a valid `X-API-Key` header on write requests (anything but GET, HEAD and OPTIONS)
+/// to whatever it wraps, which is the API scope. Wrapped around a resource with `with_reads`,
+/// it guards every request to it instead, which is how the reads that enumerate links (export,
+/// listing and top links) are protected. Being wrapped around routes rather than matching path
+/// strings, it applies to whatever the router resolved, however the path was encoded.
+/// Other reads stay public, and with no keys configured every request passes.
+pub struct ApiKeyAuth {
+    keys: Rc<Vec<String>>,
+    reads: bool,
+}
+
+impl ApiKeyAuth {
+    pub fn new(keys: Vec<String>) -> Self {
+        ApiKeyAuth { keys: Rc::new(keys), reads: false }
+    }
+
+    /// Requires the key for reads as well, on whatever this wraps.
+    pub fn with_reads(mut self) -> Self {
+        self.reads = true;
+        self
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiKeyAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = ApiKeyAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiKeyAuthMiddleware {
+            service: Rc::new(service),
+            keys: Rc::clone(&self.keys),
+            reads: self.reads,
+        }))
+    }
+}
+
+pub struct ApiKeyAuthMiddleware<S> {
+    service: Rc<S>,
+    keys: Rc<Vec<String>>,
+    reads: bool,
+}
+
+impl<S> ApiKeyAuthMiddleware<S> {
+    fn is_protected(&self, req: &ServiceRequest) -> bool {
+        self.reads || !matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS)
+    }
+
+    fn is_authorized(&self, req: &ServiceRequest) -> bool {
+        let Some(provided) = req.headers().get(API_KEY_HEADER) else {
+            return false;
+        };
+
+        self.keys
+            .iter()
+            .any(|key| verify_slices_are_equal(key.as_bytes(), provided.as_bytes()).is_ok())
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for ApiKeyAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if !self.keys.is_empty() && self.is_protected(&req) && !self.is_authorized(&req) {
+            let response = HttpResponse::Unauthorized()
+                .json(serde_json::json!({ "error": "Missing or invalid API key" }));
+            return Box::pin(async move { Ok(req.into_response(response).map_into_right_body()) });
+        }
+
+        let svc = self.service.clone();
+        Box::pin(async move { svc.call(req).await.map(ServiceResponse::map_into_left_body) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    async fn status(method: Method, path: &str, key: Option<&str>) -> u16 {
+        let keys = || vec!["secret".to_string()];
+        let read = |path: &str| web::resource(path).wrap(ApiKeyAuth::new(keys()).with_reads()).to(HttpResponse::Ok);
+        // Wired like `main`: batch expansion is registered outside the guarded API scope
+        let app = test::init_service(
+            App::new()
+                .route("/api/v1/expand/batch", web::post().to(HttpResponse::Ok))
+                .service(
+                    web::scope("/api/v1")
+                        .wrap(ApiKeyAuth::new(keys()))
+                        .service(read("/urls"))
+                        .service(read("/top"))
+                        .service(read("/export"))
+                        .default_service(web::to(HttpResponse::Ok)),
+                )
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let mut req = test::TestRequest::default().method(method).uri(path);
+        if let Some(key) = key {
+            req = req.insert_header((API_KEY_HEADER, key));
+        }
+        test::call_service(&app, req.to_request()).await.status().as_u16()
+    }
+
+    #[actix_web::test]
+    async fn enumerating_reads_need_a_key() {
+        for path in ["/api/v1/export", "/api/v1/urls?limit=5", "/api/v1/top"] {
+            assert_eq!(status(Method::GET, path, None).await, 401, "{}", path);
+            assert_eq!(status(Method::GET, path, Some("secret")).await, 200, "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn percent_encoded_paths_to_enumerating_reads_need_a_key() {
+        for path in ["/api/v1/%75rls", "/api/v1/%74op", "/api/v1/%65xport", "/%61pi/v1/urls"] {
+            assert_eq!(status(Method::GET, path, None).await, 401, "{}", path);
+            assert_eq!(status(Method::GET, path, Some("secret")).await, 200, "{}", path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn percent_encoded_paths_to_writes_need_a_key() {
+        for (method, path) in [
+            (Method::POST, "/%61pi/v1/shorten"),
+            (Method::DELETE, "/%61pi/v1/abc123"),
+            (Method::POST, "/%61pi/v1/admin/compact"),
+            (Method::PUT, "/api/%761/abc123"),
+        ] {
+            assert_eq!(status(method.clone(), path, None).await, 401, "{} {}", method, path);
+            assert_eq!(status(method.clone(), path, Some("secret")).await, 200, "{} {}", method, path);
+        }
+        assert_eq!(status(Method::POST, "/api/v1/%65xpand/batch", None).await, 200);
+    }
+
+    #[actix_web::test]
+    async fn other_reads_stay_public() {
+        assert_eq!(status(Method::GET, "/api/v1/urls/abc123", None).await, 200);
+        assert_eq!(status(Method::POST, "/api/v1/expand/batch", None).await, 200);
+        assert_eq!(status(Method::POST, "/api/v1/shorten", None).await, 401);
+    }
+}
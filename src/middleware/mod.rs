@@ -1 +1,4 @@
-pub mod url_validator; 
\ No newline at end of file
+pub mod url_validator;
+pub mod api_key;
+pub mod security_headers;
+pub mod request_id;
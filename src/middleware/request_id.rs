@@ -0,0 +1,91 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue},
+    Error,
+};
+use futures::future::{ready, Ready, LocalBoxFuture};
+use std::rc::Rc;
+use tracing::Instrument;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Incoming ids longer than this are replaced rather than echoed into logs and responses.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+const GENERATED_ID_LENGTH: usize = 21;
+
+/// Runs every request inside a tracing span carrying its request id, so all logs emitted
+/// while handling it can be correlated. The id is taken from an incoming `X-Request-Id`
+/// header when it looks sane, generated otherwise, and echoed back in the response.
+pub struct RequestId;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestId
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RequestIdMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestIdMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestIdMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S> RequestIdMiddleware<S> {
+    fn request_id(req: &ServiceRequest) -> HeaderValue {
+        req.headers()
+            .get(&REQUEST_ID_HEADER)
+            .filter(|value| {
+                let bytes = value.as_bytes();
+                !bytes.is_empty()
+                    && bytes.len() <= MAX_REQUEST_ID_LENGTH
+                    && bytes.iter().all(|b| b.is_ascii_graphic())
+            })
+            .cloned()
+            .unwrap_or_else(|| {
+                HeaderValue::from_str(&nanoid::nanoid!(GENERATED_ID_LENGTH))
+                    .expect("nanoid output is a valid header value")
+            })
+    }
+}
+
+impl<S, B> Service<ServiceRequest> for RequestIdMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let request_id = Self::request_id(&req);
+        let span = tracing::info_span!(
+            "request",
+            request_id = request_id.to_str().unwrap_or_default(),
+            method = %req.method(),
+            path = req.path(),
+        );
+
+        Box::pin(
+            async move {
+                let mut res = svc.call(req).await?;
+                res.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
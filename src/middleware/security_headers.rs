@@ -0,0 +1,87 @@
+use actix_web::{
+    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    http::header::{HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS},
+    Error,
+};
+use futures::future::{ready, Ready, LocalBoxFuture};
+use std::rc::Rc;
+
+use crate::config::HeadersConfig;
+
+/// Adds the configured security headers to every response. Headers a handler already set are left alone.
+pub struct SecurityHeaders {
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl SecurityHeaders {
+    /// Header values are checked by `Config` validation; any that still fail to parse are skipped.
+    pub fn new(config: &HeadersConfig) -> Self {
+        let mut headers = Vec::new();
+        if config.nosniff {
+            headers.push((X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")));
+        }
+        if config.frame_deny {
+            headers.push((X_FRAME_OPTIONS, HeaderValue::from_static("DENY")));
+        }
+        if let Some(value) = config.referrer_policy.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.push((REFERRER_POLICY, value));
+        }
+        if let Some(value) = config.content_security_policy.as_deref().and_then(|v| HeaderValue::from_str(v).ok()) {
+            headers.push((CONTENT_SECURITY_POLICY, value));
+        }
+
+        SecurityHeaders { headers: Rc::new(headers) }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for SecurityHeaders
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SecurityHeadersMiddleware {
+            service: Rc::new(service),
+            headers: Rc::clone(&self.headers),
+        }))
+    }
+}
+
+pub struct SecurityHeadersMiddleware<S> {
+    service: Rc<S>,
+    headers: Rc<Vec<(HeaderName, HeaderValue)>>,
+}
+
+impl<S, B> Service<ServiceRequest> for SecurityHeadersMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let svc = self.service.clone();
+        let headers = Rc::clone(&self.headers);
+
+        Box::pin(async move {
+            let mut res = svc.call(req).await?;
+            let response_headers = res.headers_mut();
+            for (name, value) in headers.iter() {
+                if !response_headers.contains_key(name) {
+                    response_headers.insert(name.clone(), value.clone());
+                }
+            }
+            Ok(res)
+        })
+    }
+}
@@ -1,16 +1,20 @@
 use actix_web::{
-    dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform},
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    web::Bytes,
     Error, HttpResponse,
 };
 use futures::future::{ready, Ready, LocalBoxFuture};
 use std::rc::Rc;
 use url::Url;
 
-pub struct UrlValidator;
+pub struct UrlValidator {
+    max_url_length: usize,
+}
 
 impl UrlValidator {
-    pub fn new() -> Self {
-        UrlValidator
+    pub fn new(max_url_length: usize) -> Self {
+        UrlValidator { max_url_length }
     }
 }
 
@@ -19,7 +23,7 @@ where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type InitError = ();
     type Transform = UrlValidatorMiddleware<S>;
@@ -28,12 +32,30 @@ where
     fn new_transform(&self, service: S) -> Self::Future {
         ready(Ok(UrlValidatorMiddleware {
             service: Rc::new(service),
+            max_url_length: self.max_url_length,
         }))
     }
 }
 
 pub struct UrlValidatorMiddleware<S> {
     service: Rc<S>,
+    max_url_length: usize,
+}
+
+impl<S> UrlValidatorMiddleware<S> {
+    fn rejection(body: &[u8], max_url_length: usize) -> Option<String> {
+        let payload = serde_json::from_slice::<serde_json::Value>(body).ok()?;
+        let url = payload.get("url").and_then(|v| v.as_str())?;
+
+        // Count bytes rather than chars so multibyte URLs can't slip past the limit
+        if url.len() > max_url_length {
+            return Some(format!("URL exceeds maximum length of {} bytes", max_url_length));
+        }
+        if Url::parse(url).is_err() {
+            return Some("Invalid URL format".to_string());
+        }
+        None
+    }
 }
 
 impl<S, B> Service<ServiceRequest> for UrlValidatorMiddleware<S>
@@ -41,33 +63,32 @@ where
     S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
     B: 'static,
 {
-    type Response = ServiceResponse<B>;
+    type Response = ServiceResponse<EitherBody<B>>;
     type Error = Error;
     type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     forward_ready!(service);
 
-    fn call(&self, req: ServiceRequest) -> Self::Future {
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
         let svc = self.service.clone();
+        let max_url_length = self.max_url_length;
 
         Box::pin(async move {
             if req.path() == "/api/v1/shorten" && req.method() == actix_web::http::Method::POST {
-                let mut payload = req.extract::<actix_web::web::Json<serde_json::Value>>().await;
-                if let Ok(body) = &mut payload {
-                    if let Some(url) = body.get("url").and_then(|v| v.as_str()) {
-                        if Url::parse(url).is_err() {
-                            let response = HttpResponse::BadRequest()
-                                .json("Invalid URL format");
-                            return Ok(ServiceResponse::new(
-                                req.into_parts().0,
-                                response,
-                            ));
-                        }
-                    }
+                let body = req.extract::<Bytes>().await?;
+                if let Some(message) = Self::rejection(&body, max_url_length) {
+                    let response = HttpResponse::BadRequest()
+                        .json(serde_json::json!({ "error": message }));
+                    return Ok(req.into_response(response).map_into_right_body());
                 }
+
+                // Hand the buffered body back so the handler can still read it
+                req.set_payload(Payload::Stream {
+                    payload: Box::pin(futures::stream::once(async move { Ok(body) })),
+                });
             }
             
-            svc.call(req).await
+            svc.call(req).await.map(ServiceResponse::map_into_left_body)
         })
     }
-} 
\ No newline at end of file
+}
\ No newline at end of file
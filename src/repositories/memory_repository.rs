@@ -0,0 +1,361 @@
+use async_trait::async_trait;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+use crate::repositories::url_repository::{select_top, Entry, EntryIter, RepositoryError, UrlRepository};
+
+#[derive(Default)]
+struct MemoryState {
+    mappings: HashMap<String, Vec<u8>>,
+    url_index: HashMap<Vec<u8>, String>,
+    clicks: HashMap<String, u64>,
+    last_accessed: HashMap<String, u64>,
+    visitors: HashMap<String, HashSet<Vec<u8>>>,
+    /// Keyed by short code, then dimension; buckets are ordered to match sled's key order.
+    breakdown: HashMap<String, HashMap<String, BTreeMap<String, u64>>>,
+    daily_clicks: HashMap<String, BTreeMap<u32, u64>>,
+    expiry: BTreeSet<(u64, String)>,
+    tags: HashMap<String, BTreeSet<String>>,
+    /// `None` until the first code is generated.
+    sequence: Option<u64>,
+}
+
+/// Non-persistent repository for tests and throwaway instances.
+/// All data lives behind a single lock, so multi-map writes are atomic.
+#[derive(Default)]
+pub struct InMemoryUrlRepository {
+    state: RwLock<MemoryState>,
+}
+
+impl InMemoryUrlRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, MemoryState>, RepositoryError> {
+        self.state
+            .read()
+            .map_err(|_| RepositoryError::Storage("In-memory store lock poisoned".to_string()))
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, MemoryState>, RepositoryError> {
+        self.state
+            .write()
+            .map_err(|_| RepositoryError::Storage("In-memory store lock poisoned".to_string()))
+    }
+}
+
+#[async_trait]
+impl UrlRepository for InMemoryUrlRepository {
+    async fn store(&self, short_url: &str, long_url: &str, url_hash: Option<&[u8]>) -> Result<(), RepositoryError> {
+        let mut state = self.write()?;
+        state.mappings.insert(short_url.to_string(), long_url.as_bytes().to_vec());
+        if let Some(url_hash) = url_hash {
+            state.url_index.insert(url_hash.to_vec(), short_url.to_string());
+        }
+        Ok(())
+    }
+
+    async fn store_if_absent(&self, short_url: &str, long_url: &str, url_hash: Option<&[u8]>) -> Result<bool, RepositoryError> {
+        let mut state = self.write()?;
+        if state.mappings.contains_key(short_url) {
+            return Ok(false);
+        }
+        state.mappings.insert(short_url.to_string(), long_url.as_bytes().to_vec());
+        if let Some(url_hash) = url_hash {
+            state.url_index.insert(url_hash.to_vec(), short_url.to_string());
+        }
+        Ok(true)
+    }
+
+    async fn replace(&self, short_url: &str, long_url: &str, old_hash: Option<&[u8]>, new_hash: Option<&[u8]>) -> Result<bool, RepositoryError> {
+        let mut state = self.write()?;
+        let Some(value) = state.mappings.get_mut(short_url) else {
+            return Ok(false);
+        };
+
+        *value = long_url.as_bytes().to_vec();
+        if let Some(old_hash) = old_hash {
+            if state.url_index.get(old_hash).is_some_and(|code| code == short_url) {
+                state.url_index.remove(old_hash);
+            }
+        }
+        if let Some(new_hash) = new_hash {
+            state.url_index.insert(new_hash.to_vec(), short_url.to_string());
+        }
+        Ok(true)
+    }
+
+    async fn compare_and_swap(&self, short_url: &str, current: &str, new: &str) -> Result<bool, RepositoryError> {
+        let mut state = self.write()?;
+        match state.mappings.get_mut(short_url) {
+            Some(value) if value.as_slice() == current.as_bytes() => {
+                *value = new.as_bytes().to_vec();
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn flush(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    async fn size_on_disk(&self) -> Result<u64, RepositoryError> {
+        Ok(0)
+    }
+
+    async fn compact(&self) -> Result<(), RepositoryError> {
+        Ok(())
+    }
+
+    async fn remove(&self, short_url: &str, url_hash: Option<&[u8]>) -> Result<bool, RepositoryError> {
+        let mut state = self.write()?;
+        let removed = state.mappings.remove(short_url).is_some();
+        if let Some(url_hash) = url_hash {
+            if state.url_index.get(url_hash).is_some_and(|code| code == short_url) {
+                state.url_index.remove(url_hash);
+            }
+        }
+        state.clicks.remove(short_url);
+        state.last_accessed.remove(short_url);
+        state.visitors.remove(short_url);
+        state.breakdown.remove(short_url);
+        state.daily_clicks.remove(short_url);
+        Ok(removed)
+    }
+
+    async fn get(&self, short_url: &str) -> Result<Option<String>, RepositoryError> {
+        Ok(self.read()?
+            .mappings
+            .get(short_url)
+            .map(|bytes| String::from_utf8_lossy(bytes).into_owned()))
+    }
+
+    async fn find_by_hash(&self, url_hash: &[u8]) -> Result<Option<String>, RepositoryError> {
+        Ok(self.read()?.url_index.get(url_hash).cloned())
+    }
+
+    async fn index_if_absent(&self, url_hash: &[u8], short_url: &str) -> Result<bool, RepositoryError> {
+        let mut state = self.write()?;
+        if state.url_index.contains_key(url_hash) {
+            return Ok(false);
+        }
+        state.url_index.insert(url_hash.to_vec(), short_url.to_string());
+        Ok(true)
+    }
+
+    async fn scan_prefix(&self, prefix: &str) -> Result<EntryIter, RepositoryError> {
+        let mut entries: Vec<Entry> = self.read()?
+            .mappings
+            .iter()
+            .filter(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| Entry {
+                key: key.as_bytes().to_vec(),
+                value: value.clone(),
+            })
+            .collect();
+
+        // Match sled's lexicographic key ordering
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(Box::new(entries.into_iter().map(Ok)))
+    }
+
+    async fn count(&self) -> Result<usize, RepositoryError> {
+        Ok(self.read()?.mappings.len())
+    }
+
+    async fn get_clicks(&self, short_url: &str) -> Result<u64, RepositoryError> {
+        Ok(self.read()?.clicks.get(short_url).copied().unwrap_or(0))
+    }
+
+    async fn set_clicks(&self, short_url: &str, clicks: u64) -> Result<(), RepositoryError> {
+        self.write()?.clicks.insert(short_url.to_string(), clicks);
+        Ok(())
+    }
+
+    async fn increment_clicks(&self, short_url: &str) -> Result<u64, RepositoryError> {
+        let mut state = self.write()?;
+        let count = state.clicks.entry(short_url.to_string()).or_insert(0);
+        *count += 1;
+        Ok(*count)
+    }
+
+    async fn increment_daily_clicks(&self, short_url: &str, day: u32) -> Result<(), RepositoryError> {
+        *self.write()?
+            .daily_clicks
+            .entry(short_url.to_string())
+            .or_default()
+            .entry(day)
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    async fn get_daily_clicks(&self, short_url: &str, since: u32) -> Result<Vec<(u32, u64)>, RepositoryError> {
+        Ok(self.read()?
+            .daily_clicks
+            .get(short_url)
+            .map(|days| days.range(since..).map(|(day, count)| (*day, *count)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn purge_daily_clicks(&self, before: u32) -> Result<usize, RepositoryError> {
+        let mut state = self.write()?;
+        let mut purged = 0;
+        for days in state.daily_clicks.values_mut() {
+            let kept = days.split_off(&before);
+            purged += days.len();
+            *days = kept;
+        }
+        state.daily_clicks.retain(|_, days| !days.is_empty());
+        Ok(purged)
+    }
+
+    async fn top_clicks(&self, limit: usize) -> Result<Vec<(String, u64)>, RepositoryError> {
+        let state = self.read()?;
+        select_top(state.clicks.iter().map(|(code, clicks)| Ok((code.clone(), *clicks))), limit)
+    }
+
+    async fn increment_clicks_within(&self, short_url: &str, limit: u64) -> Result<Option<u64>, RepositoryError> {
+        let mut state = self.write()?;
+        let count = state.clicks.entry(short_url.to_string()).or_insert(0);
+        if *count >= limit {
+            return Ok(None);
+        }
+        *count += 1;
+        Ok(Some(*count))
+    }
+
+    async fn touch(&self, short_url: &str, accessed_at: u64) -> Result<(), RepositoryError> {
+        self.write()?.last_accessed.insert(short_url.to_string(), accessed_at);
+        Ok(())
+    }
+
+    async fn get_last_accessed(&self, short_url: &str) -> Result<Option<u64>, RepositoryError> {
+        Ok(self.read()?.last_accessed.get(short_url).copied())
+    }
+
+    async fn record_visitor(&self, short_url: &str, visitor: &[u8]) -> Result<(), RepositoryError> {
+        self.write()?
+            .visitors
+            .entry(short_url.to_string())
+            .or_default()
+            .insert(visitor.to_vec());
+        Ok(())
+    }
+
+    async fn count_visitors(&self, short_url: &str) -> Result<u64, RepositoryError> {
+        Ok(self.read()?.visitors.get(short_url).map_or(0, |set| set.len() as u64))
+    }
+
+    async fn increment_breakdown(&self, short_url: &str, dimension: &str, bucket: &str) -> Result<(), RepositoryError> {
+        *self.write()?
+            .breakdown
+            .entry(short_url.to_string())
+            .or_default()
+            .entry(dimension.to_string())
+            .or_default()
+            .entry(bucket.to_string())
+            .or_insert(0) += 1;
+        Ok(())
+    }
+
+    async fn get_breakdown(&self, short_url: &str, dimension: &str) -> Result<Vec<(String, u64)>, RepositoryError> {
+        Ok(self.read()?
+            .breakdown
+            .get(short_url)
+            .and_then(|dimensions| dimensions.get(dimension))
+            .map(|buckets| buckets.iter().map(|(bucket, count)| (bucket.clone(), *count)).collect())
+            .unwrap_or_default())
+    }
+
+    async fn schedule_expiry(&self, short_url: &str, expires_at: u64) -> Result<(), RepositoryError> {
+        self.write()?.expiry.insert((expires_at, short_url.to_string()));
+        Ok(())
+    }
+
+    async fn due_expirations(&self, now: u64, limit: usize) -> Result<Vec<(u64, String)>, RepositoryError> {
+        Ok(self.read()?
+            .expiry
+            .iter()
+            .take_while(|(expires_at, _)| *expires_at <= now)
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn unschedule_expiry(&self, short_url: &str, expires_at: u64) -> Result<(), RepositoryError> {
+        self.write()?.expiry.remove(&(expires_at, short_url.to_string()));
+        Ok(())
+    }
+
+    async fn tag_url(&self, tag: &str, short_url: &str) -> Result<(), RepositoryError> {
+        self.write()?
+            .tags
+            .entry(tag.to_string())
+            .or_default()
+            .insert(short_url.to_string());
+        Ok(())
+    }
+
+    async fn tagged_urls(&self, tag: &str) -> Result<Vec<String>, RepositoryError> {
+        Ok(self.read()?
+            .tags
+            .get(tag)
+            .map(|codes| codes.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+
+    async fn untag_url(&self, tag: &str, short_url: &str) -> Result<(), RepositoryError> {
+        let mut state = self.write()?;
+        if let Some(codes) = state.tags.get_mut(tag) {
+            codes.remove(short_url);
+            if codes.is_empty() {
+                state.tags.remove(tag);
+            }
+        }
+        Ok(())
+    }
+
+    async fn next_sequence(&self, start: u64) -> Result<u64, RepositoryError> {
+        let mut state = self.write()?;
+        let value = state.sequence.map_or(start, |value| value.saturating_add(1));
+        state.sequence = Some(value);
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[actix_web::test]
+    async fn store_get_remove_round_trip() {
+        let repository = InMemoryUrlRepository::new();
+        repository.store("abc123", "value", Some(b"hash")).await.unwrap();
+
+        assert_eq!(repository.get("abc123").await.unwrap().as_deref(), Some("value"));
+        assert_eq!(repository.find_by_hash(b"hash").await.unwrap().as_deref(), Some("abc123"));
+
+        assert!(repository.remove("abc123", Some(b"hash")).await.unwrap());
+        assert_eq!(repository.get("abc123").await.unwrap(), None);
+        assert_eq!(repository.find_by_hash(b"hash").await.unwrap(), None);
+        assert!(!repository.remove("abc123", Some(b"hash")).await.unwrap());
+    }
+
+    #[actix_web::test]
+    async fn scan_prefix_matches_only_the_prefix() {
+        let repository = InMemoryUrlRepository::new();
+        for code in ["docs", "docs-api", "blog"] {
+            repository.store(code, code, None).await.unwrap();
+        }
+
+        let keys: Vec<Vec<u8>> = repository
+            .scan_prefix("docs")
+            .await
+            .unwrap()
+            .map(|entry| entry.unwrap().key)
+            .collect();
+        assert_eq!(keys, vec![b"docs".to_vec(), b"docs-api".to_vec()]);
+        assert_eq!(repository.scan_prefix("").await.unwrap().count(), 3);
+    }
+}
@@ -1 +1,3 @@
-pub mod url_repository; 
\ No newline at end of file
+pub mod url_repository;
+pub mod url_record;
+pub mod memory_repository;
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::repositories::url_repository::RepositoryError;
+
+/// Value stored against each short code.
+///
+/// Entries written before metadata existed hold only the base64 ciphertext;
+/// `decode` detects that format and maps it onto a record without metadata.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UrlRecord {
+    pub destination: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Unix millis; absent for entries written before creation times were tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    /// Single-use links resolve once and are then marked `consumed`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub one_time: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub consumed: bool,
+    /// Clicks after which the link stops resolving; the count itself lives in the clicks tree.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_clicks: Option<u64>,
+    /// Hash of the token that allows editing or deleting the link without an API key.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub manage_token_hash: Option<String>,
+    /// Set by a delete; the tombstoned link stops resolving but can be restored until the
+    /// undo window passes and the cleanup sweep removes it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<u64>,
+    /// Free-form note about the link, encrypted like `destination`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Lowercased labels, mirrored in the tag index for filtering. Kept in plain text since
+    /// the index needs them anyway.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+}
+
+impl UrlRecord {
+    pub fn new(destination: String) -> Self {
+        Self {
+            destination,
+            expires_at: None,
+            created_at: None,
+            one_time: false,
+            consumed: false,
+            max_clicks: None,
+            manage_token_hash: None,
+            deleted_at: None,
+            description: None,
+            tags: Vec::new(),
+        }
+    }
+
+    pub fn decode(raw: &str) -> Result<Self, RepositoryError> {
+        // Base64 never starts with '{', so anything else is a legacy entry
+        if !raw.starts_with('{') {
+            return Ok(Self::new(raw.to_string()));
+        }
+
+        serde_json::from_str(raw)
+            .map_err(|e| RepositoryError::Storage(format!("Failed to decode URL record: {}", e)))
+    }
+
+    pub fn encode(&self) -> Result<String, RepositoryError> {
+        serde_json::to_string(self)
+            .map_err(|e| RepositoryError::Storage(format!("Failed to encode URL record: {}", e)))
+    }
+
+    /// Consumed one-time links count as expired.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.consumed || self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+
+    /// Only permanent, reusable links take part in URL deduplication. Described and tagged
+    /// links are kept out too, so shortening a URL with a new note or tags doesn't return
+    /// someone else's link.
+    pub fn is_indexable(&self) -> bool {
+        self.expires_at.is_none()
+            && !self.one_time
+            && self.max_clicks.is_none()
+            && self.deleted_at.is_none()
+            && self.description.is_none()
+            && self.tags.is_empty()
+    }
+}
+
+/// Current time as unix milliseconds.
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+pub const MILLIS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Days since the unix epoch (UTC) for a unix-millis timestamp.
+pub fn day_number(millis: u64) -> u32 {
+    (millis / MILLIS_PER_DAY) as u32
+}
+
+/// Formats a day number as a `yyyy-mm-dd` UTC date (Howard Hinnant's civil-from-days).
+pub fn format_day(day: u32) -> String {
+    let z = day as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = doy - (153 * mp + 2) / 5 + 1;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 };
+    let y = yoe + era * 400 + i64::from(m <= 2);
+    format!("{:04}-{:02}-{:02}", y, m, d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn days_format_as_utc_dates() {
+        assert_eq!(format_day(0), "1970-01-01");
+        assert_eq!(format_day(day_number(951_782_400_000)), "2000-02-29");
+        assert_eq!(format_day(day_number(1_767_225_599_999)), "2025-12-31");
+    }
+}
@@ -1,15 +1,42 @@
-use sled::Db;
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+use sled::{Db, Tree, transaction::TransactionError, Transactional};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::warn;
+
+const CLICKS_TREE: &str = "clicks";
+const URL_INDEX_TREE: &str = "url_to_code";
+const EXPIRY_TREE: &str = "expiry";
+const LAST_ACCESSED_TREE: &str = "last_accessed";
+const VISITORS_TREE: &str = "visitors";
+const BREAKDOWN_TREE: &str = "click_breakdown";
+const DAILY_CLICKS_TREE: &str = "daily_clicks";
+const TAGS_TREE: &str = "tags";
+const SEQUENCE_TREE: &str = "sequence";
+const CODE_SEQUENCE_KEY: &[u8] = b"code";
+/// Wait before the first retry of a transient failure; each further retry doubles it.
+const RETRY_BASE_DELAY: Duration = Duration::from_millis(10);
 
 #[derive(Debug)]
 pub enum RepositoryError {
     Storage(String),
+    /// An IO failure that may succeed when tried again.
+    Transient(String),
+}
+
+impl RepositoryError {
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Transient(_))
+    }
 }
 
 impl std::fmt::Display for RepositoryError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Storage(msg) => write!(f, "Storage error: {}", msg),
+            Self::Storage(msg) | Self::Transient(msg) => write!(f, "Storage error: {}", msg),
         }
     }
 }
@@ -20,8 +47,137 @@ impl std::error::Error for RepositoryError {
     }
 }
 
+pub type EntryIter = Box<dyn Iterator<Item = Result<Entry, RepositoryError>> + Send>;
+pub type EntryStream = BoxStream<'static, Result<Entry, RepositoryError>>;
+
+/// Storage backend for URL mappings, the reverse URL index and click counters.
+/// Writes are not flushed implicitly; callers decide when durability is needed.
+#[async_trait]
+pub trait UrlRepository: Send + Sync {
+    /// Writes a mapping, overwriting any existing entry for the short code.
+    async fn store(&self, short_url: &str, long_url: &str, url_hash: Option<&[u8]>) -> Result<(), RepositoryError>;
+
+    /// Writes a mapping only if the short code is unused.
+    /// Returns `false` when the code is already taken.
+    async fn store_if_absent(&self, short_url: &str, long_url: &str, url_hash: Option<&[u8]>) -> Result<bool, RepositoryError>;
+
+    /// Overwrites an existing mapping, moving its index entry from `old_hash` to `new_hash`.
+    /// Click counts are kept. Returns `false` when the short code did not exist.
+    async fn replace(&self, short_url: &str, long_url: &str, old_hash: Option<&[u8]>, new_hash: Option<&[u8]>) -> Result<bool, RepositoryError>;
+
+    /// Swaps a mapping's value only if it still equals `current`.
+    /// Returns `false` when another writer changed or removed it first.
+    async fn compare_and_swap(&self, short_url: &str, current: &str, new: &str) -> Result<bool, RepositoryError>;
+
+    async fn flush(&self) -> Result<(), RepositoryError>;
+
+    /// Bytes the store occupies on disk; `0` for backends that don't persist.
+    async fn size_on_disk(&self) -> Result<u64, RepositoryError>;
+
+    /// Rewrites live data so space held by deleted and overwritten entries can be reclaimed.
+    async fn compact(&self) -> Result<(), RepositoryError>;
+
+    /// Removes a mapping along with its index and analytics entries.
+    /// Returns `false` when the short code did not exist.
+    async fn remove(&self, short_url: &str, url_hash: Option<&[u8]>) -> Result<bool, RepositoryError>;
+
+    async fn get(&self, short_url: &str) -> Result<Option<String>, RepositoryError>;
+
+    async fn find_by_hash(&self, url_hash: &[u8]) -> Result<Option<String>, RepositoryError>;
+
+    async fn index_if_absent(&self, url_hash: &[u8], short_url: &str) -> Result<bool, RepositoryError>;
+
+    /// Iterates forward mappings only; index and analytics data are never included.
+    async fn scan_prefix(&self, prefix: &str) -> Result<EntryIter, RepositoryError>;
+
+    /// `scan_prefix` as a stream, so async callers can process entries one at a time and
+    /// stop early; entries are read from storage only as the stream is polled.
+    async fn stream_prefix(&self, prefix: &str) -> Result<EntryStream, RepositoryError> {
+        Ok(stream::iter(self.scan_prefix(prefix).await?).boxed())
+    }
+
+    /// Number of forward mappings; index and analytics entries are not counted.
+    async fn count(&self) -> Result<usize, RepositoryError>;
+
+    async fn get_clicks(&self, short_url: &str) -> Result<u64, RepositoryError>;
+
+    /// Overwrites the click count, e.g. with the count from a backup being restored.
+    async fn set_clicks(&self, short_url: &str, clicks: u64) -> Result<(), RepositoryError>;
+
+    /// Increments as one atomic step, so concurrent redirects never lose a click.
+    /// Returns the new count.
+    async fn increment_clicks(&self, short_url: &str) -> Result<u64, RepositoryError>;
+
+    /// Counts a click in the bucket for `day` (days since the unix epoch), atomically like `increment_clicks`.
+    async fn increment_daily_clicks(&self, short_url: &str, day: u32) -> Result<(), RepositoryError>;
+
+    /// Returns `(day, clicks)` for the days from `since` onwards that have clicks, oldest first.
+    async fn get_daily_clicks(&self, short_url: &str, since: u32) -> Result<Vec<(u32, u64)>, RepositoryError>;
+
+    /// Drops daily buckets for days before `before`, across all codes. Returns how many were removed.
+    async fn purge_daily_clicks(&self, before: u32) -> Result<usize, RepositoryError>;
+
+    /// Returns up to `limit` `(short_code, clicks)` pairs with the most clicks, highest first.
+    /// Reads every counter, but holds only `limit` of them at a time.
+    async fn top_clicks(&self, limit: usize) -> Result<Vec<(String, u64)>, RepositoryError>;
+
+    /// Increments the click count only while it is below `limit`, as one atomic step.
+    /// Returns the new count, or `None` once the limit has been reached.
+    async fn increment_clicks_within(&self, short_url: &str, limit: u64) -> Result<Option<u64>, RepositoryError>;
+
+    /// Records when a mapping was last resolved, overwriting the previous value.
+    async fn touch(&self, short_url: &str, accessed_at: u64) -> Result<(), RepositoryError>;
+
+    /// Returns when a mapping was last resolved, or `None` if it never was.
+    async fn get_last_accessed(&self, short_url: &str) -> Result<Option<u64>, RepositoryError>;
+
+    /// Records a hashed visitor id for a mapping; repeat visits by the same id are not counted twice.
+    async fn record_visitor(&self, short_url: &str, visitor: &[u8]) -> Result<(), RepositoryError>;
+
+    async fn count_visitors(&self, short_url: &str) -> Result<u64, RepositoryError>;
+
+    /// Counts a click under `bucket` of one breakdown `dimension` (e.g. referrer host),
+    /// atomically like `increment_clicks`.
+    async fn increment_breakdown(&self, short_url: &str, dimension: &str, bucket: &str) -> Result<(), RepositoryError>;
+
+    /// Returns the `(bucket, clicks)` pairs recorded for one dimension, ordered by bucket.
+    async fn get_breakdown(&self, short_url: &str, dimension: &str) -> Result<Vec<(String, u64)>, RepositoryError>;
+
+    /// Records when a mapping stops resolving so sweeps can find it without a full scan.
+    async fn schedule_expiry(&self, short_url: &str, expires_at: u64) -> Result<(), RepositoryError>;
+
+    /// Returns up to `limit` scheduled `(expires_at, short_code)` pairs due at or before `now`, oldest first.
+    async fn due_expirations(&self, now: u64, limit: usize) -> Result<Vec<(u64, String)>, RepositoryError>;
+
+    async fn unschedule_expiry(&self, short_url: &str, expires_at: u64) -> Result<(), RepositoryError>;
+
+    /// Adds a mapping to a tag's index so listings can filter by tag without a full scan.
+    async fn tag_url(&self, tag: &str, short_url: &str) -> Result<(), RepositoryError>;
+
+    /// Returns the codes indexed under a tag, ordered by code. Entries may be stale, so
+    /// callers check each mapping's own tags.
+    async fn tagged_urls(&self, tag: &str) -> Result<Vec<String>, RepositoryError>;
+
+    async fn untag_url(&self, tag: &str, short_url: &str) -> Result<(), RepositoryError>;
+
+    /// Atomically advances the code sequence and returns the new value, starting from `start`
+    /// when no counter is stored yet. The new value is durable before it is returned, so values
+    /// are never handed out twice, even across crashes.
+    async fn next_sequence(&self, start: u64) -> Result<u64, RepositoryError>;
+}
+
 pub struct SledUrlRepository {
     db: Arc<Db>,
+    clicks: Tree,
+    url_index: Tree,
+    expiry: Tree,
+    last_accessed: Tree,
+    visitors: Tree,
+    breakdown: Tree,
+    daily_clicks: Tree,
+    tags: Tree,
+    sequence: Tree,
+    max_retries: u32,
 }
 
 #[derive(Debug)]
@@ -31,35 +187,244 @@ pub struct Entry {
 }
 
 impl SledUrlRepository {
-    pub fn new(db: Db) -> Self {
-        Self {
+    pub fn new(db: Db) -> Result<Self, sled::Error> {
+        let clicks = db.open_tree(CLICKS_TREE)?;
+        let url_index = db.open_tree(URL_INDEX_TREE)?;
+        let expiry = db.open_tree(EXPIRY_TREE)?;
+        let last_accessed = db.open_tree(LAST_ACCESSED_TREE)?;
+        let visitors = db.open_tree(VISITORS_TREE)?;
+        let breakdown = db.open_tree(BREAKDOWN_TREE)?;
+        let daily_clicks = db.open_tree(DAILY_CLICKS_TREE)?;
+        let tags = db.open_tree(TAGS_TREE)?;
+        let sequence = db.open_tree(SEQUENCE_TREE)?;
+
+        Ok(Self {
             db: Arc::new(db),
+            clicks,
+            url_index,
+            expiry,
+            last_accessed,
+            visitors,
+            breakdown,
+            daily_clicks,
+            tags,
+            sequence,
+            max_retries: 0,
+        })
+    }
+
+    /// Retries mapping reads and writes that fail with an IO error up to `max_retries` times.
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Runs `op`, retrying transient failures with exponential backoff. Other errors, such as
+    /// corruption, are returned at once.
+    async fn with_retries<T>(
+        &self,
+        operation: &str,
+        mut op: impl FnMut() -> Result<T, RepositoryError>,
+    ) -> Result<T, RepositoryError> {
+        let mut attempt = 0;
+        loop {
+            match op() {
+                Err(e) if e.is_transient() && attempt < self.max_retries => {
+                    attempt += 1;
+                    warn!(operation, attempt, error = %e, "Retrying storage operation");
+                    tokio::time::sleep(RETRY_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+                }
+                result => return result,
+            }
         }
     }
+}
 
-    pub async fn store(&self, short_url: &str, long_url: &str) -> Result<(), RepositoryError> {
-        self.db
-            .insert(short_url.as_bytes(), long_url.as_bytes())
-            .map_err(|e| RepositoryError::Storage(format!("Failed to store URL mapping: {}", e)))?;
-        
+/// Wraps a sled error, marking IO failures as transient.
+fn storage_error(context: &str, e: sled::Error) -> RepositoryError {
+    match e {
+        sled::Error::Io(e) => RepositoryError::Transient(format!("{}: {}", context, e)),
+        e => RepositoryError::Storage(format!("{}: {}", context, e)),
+    }
+}
+
+/// Wraps a failed transaction like `storage_error`. None of the transactions here abort
+/// explicitly, so an abort is reported as a plain storage error.
+fn transaction_error(context: &str, e: TransactionError<()>) -> RepositoryError {
+    match e {
+        TransactionError::Storage(e) => storage_error(context, e),
+        TransactionError::Abort(()) => RepositoryError::Storage(format!("{}: aborted", context)),
+    }
+}
+
+#[async_trait]
+impl UrlRepository for SledUrlRepository {
+    async fn store(&self, short_url: &str, long_url: &str, url_hash: Option<&[u8]>) -> Result<(), RepositoryError> {
+        // Write the forward mapping and the reverse index atomically
+        self.with_retries("store", || {
+            (&**self.db, &self.url_index)
+                .transaction(|(mappings, index)| {
+                    mappings.insert(short_url.as_bytes(), long_url.as_bytes())?;
+                    if let Some(url_hash) = url_hash {
+                        index.insert(url_hash, short_url.as_bytes())?;
+                    }
+                    Ok(())
+                })
+                .map_err(|e| transaction_error("Failed to store URL mapping", e))
+        })
+        .await
+    }
+
+    async fn store_if_absent(&self, short_url: &str, long_url: &str, url_hash: Option<&[u8]>) -> Result<bool, RepositoryError> {
+        // Transactions are serializable, so the existence check and the write cannot interleave with another insert
+        self.with_retries("store_if_absent", || {
+            (&**self.db, &self.url_index)
+                .transaction(|(mappings, index)| {
+                    if mappings.get(short_url.as_bytes())?.is_some() {
+                        return Ok(false);
+                    }
+                    mappings.insert(short_url.as_bytes(), long_url.as_bytes())?;
+                    if let Some(url_hash) = url_hash {
+                        index.insert(url_hash, short_url.as_bytes())?;
+                    }
+                    Ok(true)
+                })
+                .map_err(|e| transaction_error("Failed to store URL mapping", e))
+        })
+        .await
+    }
+
+    async fn compare_and_swap(&self, short_url: &str, current: &str, new: &str) -> Result<bool, RepositoryError> {
+        let result = self
+            .with_retries("compare_and_swap", || {
+                self.db
+                    .compare_and_swap(short_url.as_bytes(), Some(current.as_bytes()), Some(new.as_bytes()))
+                    .map_err(|e| storage_error("Failed to update URL mapping", e))
+            })
+            .await?;
+
+        Ok(result.is_ok())
+    }
+
+    async fn flush(&self) -> Result<(), RepositoryError> {
         self.db
             .flush_async()
             .await
-            .map_err(|e| RepositoryError::Storage(format!("Failed to flush database: {}", e)))?;
+            .map_err(|e| RepositoryError::Storage(format!("Failed to flush database, recent writes may not be durable: {}", e)))?;
             
         Ok(())
     }
 
-    pub async fn get(&self, short_url: &str) -> Result<Option<String>, RepositoryError> {
-        let result = self.db
-            .get(short_url.as_bytes())
-            .map_err(|e| RepositoryError::Storage(format!("Failed to retrieve URL mapping: {}", e)))?;
-            
+    async fn size_on_disk(&self) -> Result<u64, RepositoryError> {
+        self.db
+            .size_on_disk()
+            .map_err(|e| RepositoryError::Storage(format!("Failed to read database size: {}", e)))
+    }
+
+    async fn compact(&self) -> Result<(), RepositoryError> {
+        // sled frees a segment once none of its pages are live, so moving every live
+        // entry into fresh segments lets the sparse old ones be reclaimed
+        let db = Arc::clone(&self.db);
+        tokio::task::spawn_blocking(move || -> sled::Result<()> {
+            for name in db.tree_names() {
+                let tree = db.open_tree(&name)?;
+                for item in tree.iter() {
+                    let (key, value) = item?;
+                    // Swapping against the value just read never clobbers a concurrent write
+                    let _ = tree.compare_and_swap(&key, Some(&value), Some(&value))?;
+                }
+            }
+            db.flush()?;
+            Ok(())
+        })
+        .await
+        .map_err(|e| RepositoryError::Storage(format!("Compaction task failed: {}", e)))?
+        .map_err(|e| RepositoryError::Storage(format!("Failed to compact database: {}", e)))
+    }
+
+    async fn replace(&self, short_url: &str, long_url: &str, old_hash: Option<&[u8]>, new_hash: Option<&[u8]>) -> Result<bool, RepositoryError> {
+        self.with_retries("replace", || {
+            (&**self.db, &self.url_index)
+                .transaction(|(mappings, index)| {
+                    if mappings.get(short_url.as_bytes())?.is_none() {
+                        return Ok(false);
+                    }
+
+                    mappings.insert(short_url.as_bytes(), long_url.as_bytes())?;
+                    if let Some(old_hash) = old_hash {
+                        if index.get(old_hash)?.is_some_and(|code| code == short_url.as_bytes()) {
+                            index.remove(old_hash)?;
+                        }
+                    }
+                    if let Some(new_hash) = new_hash {
+                        index.insert(new_hash, short_url.as_bytes())?;
+                    }
+                    Ok(true)
+                })
+                .map_err(|e| transaction_error("Failed to replace URL mapping", e))
+        })
+        .await
+    }
+
+    async fn remove(&self, short_url: &str, url_hash: Option<&[u8]>) -> Result<bool, RepositoryError> {
+        // Drop the mapping together with its index and analytics entries
+        let removed = self
+            .with_retries("remove", || {
+                (&**self.db, &self.url_index, &self.clicks, &self.last_accessed)
+                    .transaction(|(mappings, index, clicks, last_accessed)| {
+                        let removed = mappings.remove(short_url.as_bytes())?;
+                        if let Some(url_hash) = url_hash {
+                            if index.get(url_hash)?.is_some_and(|code| code == short_url.as_bytes()) {
+                                index.remove(url_hash)?;
+                            }
+                        }
+                        clicks.remove(short_url.as_bytes())?;
+                        last_accessed.remove(short_url.as_bytes())?;
+                        Ok(removed.is_some())
+                    })
+                    .map_err(|e| transaction_error("Failed to remove URL mapping", e))
+            })
+            .await?;
+
+        // Transactional trees can't be iterated, so per-code analytics are cleared by prefix afterwards
+        clear_prefix(&self.visitors, &analytics_prefix(short_url))?;
+        clear_prefix(&self.breakdown, &analytics_prefix(short_url))?;
+        clear_prefix(&self.daily_clicks, &analytics_prefix(short_url))?;
+
+        Ok(removed)
+    }
+
+    async fn get(&self, short_url: &str) -> Result<Option<String>, RepositoryError> {
+        let result = self
+            .with_retries("get", || {
+                self.db
+                    .get(short_url.as_bytes())
+                    .map_err(|e| storage_error("Failed to retrieve URL mapping", e))
+            })
+            .await?;
+
         Ok(result
             .map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
     }
 
-    pub async fn scan_prefix(&self, prefix: &str) -> Result<impl Iterator<Item = Result<Entry, RepositoryError>>, RepositoryError> {
+    async fn find_by_hash(&self, url_hash: &[u8]) -> Result<Option<String>, RepositoryError> {
+        let result = self.url_index
+            .get(url_hash)
+            .map_err(|e| RepositoryError::Storage(format!("Failed to look up URL index: {}", e)))?;
+
+        Ok(result
+            .map(|bytes| String::from_utf8_lossy(&bytes).into_owned()))
+    }
+
+    async fn index_if_absent(&self, url_hash: &[u8], short_url: &str) -> Result<bool, RepositoryError> {
+        let result = self.url_index
+            .compare_and_swap(url_hash, None as Option<&[u8]>, Some(short_url.as_bytes()))
+            .map_err(|e| RepositoryError::Storage(format!("Failed to update URL index: {}", e)))?;
+
+        Ok(result.is_ok())
+    }
+
+    async fn scan_prefix(&self, prefix: &str) -> Result<EntryIter, RepositoryError> {
         let iter = self.db
             .scan_prefix(prefix.as_bytes())
             .map(|res| {
@@ -70,6 +435,497 @@ impl SledUrlRepository {
                 .map_err(|e| RepositoryError::Storage(format!("Failed to scan database: {}", e)))
             });
         
-        Ok(iter)
+        Ok(Box::new(iter))
+    }
+
+    async fn count(&self) -> Result<usize, RepositoryError> {
+        // Index and analytics data live in their own trees, so the default tree holds only mappings
+        Ok(self.db.len())
+    }
+
+    async fn get_clicks(&self, short_url: &str) -> Result<u64, RepositoryError> {
+        let result = self.clicks
+            .get(short_url.as_bytes())
+            .map_err(|e| RepositoryError::Storage(format!("Failed to retrieve click count: {}", e)))?;
+
+        Ok(result.map(|bytes| decode_counter(&bytes)).unwrap_or(0))
+    }
+
+    async fn set_clicks(&self, short_url: &str, clicks: u64) -> Result<(), RepositoryError> {
+        self.clicks
+            .insert(short_url.as_bytes(), &clicks.to_be_bytes())
+            .map_err(|e| RepositoryError::Storage(format!("Failed to set click count: {}", e)))?;
+        Ok(())
+    }
+
+    async fn increment_clicks(&self, short_url: &str) -> Result<u64, RepositoryError> {
+        // `update_and_fetch` retries the closure on contention instead of a racy get-then-insert
+        let updated = self.clicks
+            .update_and_fetch(short_url.as_bytes(), |old| {
+                let count = old.map(decode_counter).unwrap_or(0);
+                Some((count + 1).to_be_bytes().to_vec())
+            })
+            .map_err(|e| RepositoryError::Storage(format!("Failed to increment click count: {}", e)))?;
+
+        Ok(updated.map(|bytes| decode_counter(&bytes)).unwrap_or(0))
+    }
+
+    async fn increment_daily_clicks(&self, short_url: &str, day: u32) -> Result<(), RepositoryError> {
+        self.daily_clicks
+            .update_and_fetch(daily_key(short_url, day), |old| {
+                let count = old.map(decode_counter).unwrap_or(0);
+                Some((count + 1).to_be_bytes().to_vec())
+            })
+            .map_err(|e| RepositoryError::Storage(format!("Failed to increment daily clicks: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn get_daily_clicks(&self, short_url: &str, since: u32) -> Result<Vec<(u32, u64)>, RepositoryError> {
+        // Day numbers are big-endian, so one code's buckets are contiguous and in date order
+        let prefix = analytics_prefix(short_url);
+        let mut days = Vec::new();
+        for item in self.daily_clicks.range(daily_key(short_url, since)..) {
+            let (key, value) = item
+                .map_err(|e| RepositoryError::Storage(format!("Failed to read daily clicks: {}", e)))?;
+            let Some(day) = key.strip_prefix(prefix.as_slice()).and_then(decode_day) else {
+                break;
+            };
+            days.push((day, decode_counter(&value)));
+        }
+
+        Ok(days)
+    }
+
+    async fn purge_daily_clicks(&self, before: u32) -> Result<usize, RepositoryError> {
+        let mut purged = 0;
+        for item in self.daily_clicks.iter() {
+            let (key, _) = item
+                .map_err(|e| RepositoryError::Storage(format!("Failed to read daily clicks: {}", e)))?;
+            let day = key.len().checked_sub(4).and_then(|start| decode_day(&key[start..]));
+            if day.is_some_and(|day| day < before) {
+                self.daily_clicks
+                    .remove(&key)
+                    .map_err(|e| RepositoryError::Storage(format!("Failed to purge daily clicks: {}", e)))?;
+                purged += 1;
+            }
+        }
+
+        Ok(purged)
+    }
+
+    async fn top_clicks(&self, limit: usize) -> Result<Vec<(String, u64)>, RepositoryError> {
+        let counters = self.clicks.iter().map(|item| {
+            item.map(|(key, value)| (String::from_utf8_lossy(&key).into_owned(), decode_counter(&value)))
+                .map_err(|e| RepositoryError::Storage(format!("Failed to read click counts: {}", e)))
+        });
+
+        select_top(counters, limit)
     }
-} 
\ No newline at end of file
+
+    async fn increment_clicks_within(&self, short_url: &str, limit: u64) -> Result<Option<u64>, RepositoryError> {
+        // The closure may run more than once under contention; only the final run's outcome counts
+        let mut claimed = None;
+        self.clicks
+            .update_and_fetch(short_url.as_bytes(), |old| {
+                let count = old.map(decode_counter).unwrap_or(0);
+                if count >= limit {
+                    claimed = None;
+                    return old.map(<[u8]>::to_vec);
+                }
+                claimed = Some(count + 1);
+                Some((count + 1).to_be_bytes().to_vec())
+            })
+            .map_err(|e| RepositoryError::Storage(format!("Failed to increment click count: {}", e)))?;
+
+        Ok(claimed)
+    }
+
+    async fn touch(&self, short_url: &str, accessed_at: u64) -> Result<(), RepositoryError> {
+        self.last_accessed
+            .insert(short_url.as_bytes(), &accessed_at.to_be_bytes())
+            .map_err(|e| RepositoryError::Storage(format!("Failed to record last access: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn get_last_accessed(&self, short_url: &str) -> Result<Option<u64>, RepositoryError> {
+        let result = self.last_accessed
+            .get(short_url.as_bytes())
+            .map_err(|e| RepositoryError::Storage(format!("Failed to retrieve last access: {}", e)))?;
+
+        Ok(result.map(|bytes| decode_counter(&bytes)))
+    }
+
+    async fn record_visitor(&self, short_url: &str, visitor: &[u8]) -> Result<(), RepositoryError> {
+        let mut key = analytics_prefix(short_url);
+        key.extend_from_slice(visitor);
+        self.visitors
+            .insert(key, &[])
+            .map_err(|e| RepositoryError::Storage(format!("Failed to record visitor: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn count_visitors(&self, short_url: &str) -> Result<u64, RepositoryError> {
+        let mut count = 0;
+        for item in self.visitors.scan_prefix(analytics_prefix(short_url)) {
+            item.map_err(|e| RepositoryError::Storage(format!("Failed to read visitors: {}", e)))?;
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    async fn increment_breakdown(&self, short_url: &str, dimension: &str, bucket: &str) -> Result<(), RepositoryError> {
+        let mut key = breakdown_prefix(short_url, dimension);
+        key.extend_from_slice(bucket.as_bytes());
+        self.breakdown
+            .update_and_fetch(key, |old| {
+                let count = old.map(decode_counter).unwrap_or(0);
+                Some((count + 1).to_be_bytes().to_vec())
+            })
+            .map_err(|e| RepositoryError::Storage(format!("Failed to record click breakdown: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn get_breakdown(&self, short_url: &str, dimension: &str) -> Result<Vec<(String, u64)>, RepositoryError> {
+        let prefix = breakdown_prefix(short_url, dimension);
+        let mut buckets = Vec::new();
+        for item in self.breakdown.scan_prefix(&prefix) {
+            let (key, value) = item
+                .map_err(|e| RepositoryError::Storage(format!("Failed to read click breakdown: {}", e)))?;
+            let bucket = String::from_utf8_lossy(&key[prefix.len()..]).into_owned();
+            buckets.push((bucket, decode_counter(&value)));
+        }
+
+        Ok(buckets)
+    }
+
+    async fn schedule_expiry(&self, short_url: &str, expires_at: u64) -> Result<(), RepositoryError> {
+        self.expiry
+            .insert(expiry_key(short_url, expires_at), &[])
+            .map_err(|e| RepositoryError::Storage(format!("Failed to schedule expiry: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn due_expirations(&self, now: u64, limit: usize) -> Result<Vec<(u64, String)>, RepositoryError> {
+        // Keys start with the big-endian timestamp, so iteration order is expiry order
+        let mut due = Vec::new();
+        for item in self.expiry.iter() {
+            let (key, _) = item
+                .map_err(|e| RepositoryError::Storage(format!("Failed to read expiry schedule: {}", e)))?;
+            let Some((expires_at, short_url)) = decode_expiry_key(&key) else {
+                continue;
+            };
+            if expires_at > now || due.len() >= limit {
+                break;
+            }
+            due.push((expires_at, short_url));
+        }
+
+        Ok(due)
+    }
+
+    async fn unschedule_expiry(&self, short_url: &str, expires_at: u64) -> Result<(), RepositoryError> {
+        self.expiry
+            .remove(expiry_key(short_url, expires_at))
+            .map_err(|e| RepositoryError::Storage(format!("Failed to unschedule expiry: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn tag_url(&self, tag: &str, short_url: &str) -> Result<(), RepositoryError> {
+        self.tags
+            .insert(tag_key(tag, short_url), &[])
+            .map_err(|e| RepositoryError::Storage(format!("Failed to tag URL: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn tagged_urls(&self, tag: &str) -> Result<Vec<String>, RepositoryError> {
+        let prefix = tag_key(tag, "");
+        let mut codes = Vec::new();
+        for item in self.tags.scan_prefix(&prefix) {
+            let (key, _) = item
+                .map_err(|e| RepositoryError::Storage(format!("Failed to read tag index: {}", e)))?;
+            codes.push(String::from_utf8_lossy(&key[prefix.len()..]).into_owned());
+        }
+
+        Ok(codes)
+    }
+
+    async fn untag_url(&self, tag: &str, short_url: &str) -> Result<(), RepositoryError> {
+        self.tags
+            .remove(tag_key(tag, short_url))
+            .map_err(|e| RepositoryError::Storage(format!("Failed to untag URL: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn next_sequence(&self, start: u64) -> Result<u64, RepositoryError> {
+        let result = self
+            .with_retries("advance code sequence", || {
+                self.sequence
+                    .update_and_fetch(CODE_SEQUENCE_KEY, |old| {
+                        let value = old.map_or(start, |bytes| decode_counter(bytes).saturating_add(1));
+                        Some(value.to_be_bytes().to_vec())
+                    })
+                    .map_err(|e| storage_error("Failed to advance code sequence", e))
+            })
+            .await?;
+        // A code must never be handed out before its counter value is on disk
+        self.sequence
+            .flush_async()
+            .await
+            .map_err(|e| storage_error("Failed to persist code sequence", e))?;
+
+        Ok(result.map(|bytes| decode_counter(&bytes)).unwrap_or(start))
+    }
+}
+
+/// Keeps the `limit` highest counters seen, ordered by clicks descending and then by code.
+pub(crate) fn select_top(
+    counters: impl Iterator<Item = Result<(String, u64), RepositoryError>>,
+    limit: usize,
+) -> Result<Vec<(String, u64)>, RepositoryError> {
+    // Min-heap on (clicks, reversed code), so the weakest entry is the one evicted
+    let mut heap = BinaryHeap::with_capacity(limit + 1);
+    for counter in counters {
+        let (short_url, clicks) = counter?;
+        heap.push(Reverse((clicks, Reverse(short_url))));
+        if heap.len() > limit {
+            heap.pop();
+        }
+    }
+
+    Ok(heap
+        .into_sorted_vec()
+        .into_iter()
+        .map(|Reverse((clicks, Reverse(short_url)))| (short_url, clicks))
+        .collect())
+}
+
+fn expiry_key(short_url: &str, expires_at: u64) -> Vec<u8> {
+    let mut key = expires_at.to_be_bytes().to_vec();
+    key.extend_from_slice(short_url.as_bytes());
+    key
+}
+
+/// Short codes never contain NUL, so the separator keeps one code's prefix from matching another's.
+fn analytics_prefix(short_url: &str) -> Vec<u8> {
+    let mut prefix = short_url.as_bytes().to_vec();
+    prefix.push(0);
+    prefix
+}
+
+/// Tags are validated to exclude NUL, so like `analytics_prefix` the separator keeps tags apart.
+fn tag_key(tag: &str, short_url: &str) -> Vec<u8> {
+    let mut key = tag.as_bytes().to_vec();
+    key.push(0);
+    key.extend_from_slice(short_url.as_bytes());
+    key
+}
+
+fn daily_key(short_url: &str, day: u32) -> Vec<u8> {
+    let mut key = analytics_prefix(short_url);
+    key.extend_from_slice(&day.to_be_bytes());
+    key
+}
+
+fn decode_day(bytes: &[u8]) -> Option<u32> {
+    bytes.try_into().ok().map(u32::from_be_bytes)
+}
+
+fn breakdown_prefix(short_url: &str, dimension: &str) -> Vec<u8> {
+    let mut prefix = analytics_prefix(short_url);
+    prefix.extend_from_slice(dimension.as_bytes());
+    prefix.push(0);
+    prefix
+}
+
+fn clear_prefix(tree: &Tree, prefix: &[u8]) -> Result<(), RepositoryError> {
+    for item in tree.scan_prefix(prefix) {
+        let (key, _) = item
+            .map_err(|e| RepositoryError::Storage(format!("Failed to read analytics: {}", e)))?;
+        tree.remove(key)
+            .map_err(|e| RepositoryError::Storage(format!("Failed to remove analytics: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+fn decode_expiry_key(key: &[u8]) -> Option<(u64, String)> {
+    let (timestamp, short_url) = key.split_at_checked(8)?;
+    Some((decode_counter(timestamp), String::from_utf8_lossy(short_url).into_owned()))
+}
+
+fn decode_counter(bytes: &[u8]) -> u64 {
+    bytes
+        .try_into()
+        .map(u64::from_be_bytes)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::code_generator::{CodeGenerator, SequentialGenerator};
+    use std::path::PathBuf;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// A fresh database directory per test; removed again by `TestDb::drop`.
+    struct TestDb(PathBuf);
+
+    impl TestDb {
+        fn new() -> Self {
+            static NEXT: AtomicUsize = AtomicUsize::new(0);
+            let path = std::env::temp_dir().join(format!(
+                "url-repository-test-{}-{}",
+                std::process::id(),
+                NEXT.fetch_add(1, Ordering::Relaxed)
+            ));
+            let _ = std::fs::remove_dir_all(&path);
+            Self(path)
+        }
+
+        /// Reopening right after a drop can race sled's background threads, which hold the
+        /// file lock until they finish a flush, so a locked database is retried briefly.
+        fn open(&self) -> SledUrlRepository {
+            let mut attempts = 0;
+            let db = loop {
+                match sled::open(&self.0) {
+                    Ok(db) => break db,
+                    Err(sled::Error::Io(e)) if e.kind() == std::io::ErrorKind::Other && attempts < 50 => {
+                        attempts += 1;
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                    Err(e) => panic!("open sled: {:?}", e),
+                }
+            };
+            SledUrlRepository::new(db).expect("open trees")
+        }
+    }
+
+    impl Drop for TestDb {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn sequence_continues_after_restart() {
+        let db = TestDb::new();
+        let repository = db.open();
+        assert_eq!(repository.next_sequence(1000).await.unwrap(), 1000);
+        assert_eq!(repository.next_sequence(1000).await.unwrap(), 1001);
+        drop(repository);
+
+        let repository = db.open();
+        assert_eq!(repository.next_sequence(1000).await.unwrap(), 1002);
+    }
+
+    #[tokio::test]
+    async fn sequential_codes_are_not_reused_across_restarts() {
+        let db = TestDb::new();
+        let alphabet: Vec<char> = "0123456789".chars().collect();
+        let mut codes = std::collections::HashSet::new();
+        for _ in 0..3 {
+            let repository: Arc<dyn UrlRepository> = Arc::new(db.open());
+            let generator = SequentialGenerator::new(repository, alphabet.clone(), 0);
+            for _ in 0..5 {
+                let code = generator.next_code().await.unwrap();
+                assert!(codes.insert(code.clone()), "{} was handed out twice", code);
+            }
+        }
+        assert_eq!(codes.len(), 15);
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_up_to_the_limit() {
+        let db = TestDb::new();
+        let repository = db.open().with_max_retries(2);
+
+        let mut attempts = 0;
+        let result = repository
+            .with_retries("test", || {
+                attempts += 1;
+                match attempts {
+                    1 | 2 => Err(RepositoryError::Transient("disk hiccup".to_string())),
+                    _ => Ok(attempts),
+                }
+            })
+            .await;
+        assert_eq!(result.unwrap(), 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = repository
+            .with_retries("test", || {
+                attempts += 1;
+                Err(RepositoryError::Transient("disk gone".to_string()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result: Result<(), _> = repository
+            .with_retries("test", || {
+                attempts += 1;
+                Err(RepositoryError::Storage("corrupted".to_string()))
+            })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 1);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_click_increments_are_not_lost() {
+        const TASKS: u64 = 32;
+        const CLICKS_PER_TASK: u64 = 50;
+        let db = TestDb::new();
+        let repository = Arc::new(db.open());
+
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|_| {
+                let repository = Arc::clone(&repository);
+                tokio::spawn(async move {
+                    for _ in 0..CLICKS_PER_TASK {
+                        repository.increment_clicks("hot").await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for task in tasks {
+            task.await.unwrap();
+        }
+
+        assert_eq!(repository.get_clicks("hot").await.unwrap(), TASKS * CLICKS_PER_TASK);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn store_if_absent_has_a_single_winner() {
+        let db = TestDb::new();
+        let repository = Arc::new(db.open());
+
+        let claims: Vec<_> = (0..16)
+            .map(|i| {
+                let repository = Arc::clone(&repository);
+                tokio::spawn(async move {
+                    repository.store_if_absent("alias", &format!("value-{}", i), None).await.unwrap()
+                })
+            })
+            .collect();
+        let mut winners = 0;
+        for claim in claims {
+            if claim.await.unwrap() {
+                winners += 1;
+            }
+        }
+
+        assert_eq!(winners, 1);
+        assert!(repository.get("alias").await.unwrap().unwrap().starts_with("value-"));
+    }
+}
@@ -0,0 +1,121 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+use crate::config::{CodeStrategy, ShortenerConfig};
+use crate::repositories::url_repository::{RepositoryError, UrlRepository};
+
+/// Produces candidate short codes. Callers still check them against reserved words and
+/// claim them with a create-only write, so a generator never has to avoid existing codes.
+#[async_trait]
+pub trait CodeGenerator: Send + Sync {
+    async fn next_code(&self) -> Result<String, RepositoryError>;
+}
+
+/// Random fixed-length codes; unguessable, and the default.
+pub struct NanoidGenerator {
+    length: usize,
+    alphabet: Vec<char>,
+}
+
+impl NanoidGenerator {
+    pub fn new(length: usize, alphabet: Vec<char>) -> Self {
+        Self { length, alphabet }
+    }
+}
+
+#[async_trait]
+impl CodeGenerator for NanoidGenerator {
+    async fn next_code(&self) -> Result<String, RepositoryError> {
+        let length = self.length;
+        Ok(nanoid::nanoid!(length, &self.alphabet))
+    }
+}
+
+/// Writes the repository's persisted counter in the code alphabet (base62 by default), so
+/// codes are ordered by creation and easy to trace, but also easy to enumerate.
+///
+/// The counter is read and advanced in storage on every call rather than cached, and each new
+/// value is flushed before its code is used, so restarts, crashes and multiple workers all
+/// continue where the last code left off.
+pub struct SequentialGenerator {
+    repository: Arc<dyn UrlRepository>,
+    alphabet: Vec<char>,
+    start: u64,
+}
+
+impl SequentialGenerator {
+    pub fn new(repository: Arc<dyn UrlRepository>, alphabet: Vec<char>, start: u64) -> Self {
+        Self { repository, alphabet, start }
+    }
+
+    /// Most significant digit first, with `alphabet[0]` as zero.
+    fn encode(&self, mut value: u64) -> String {
+        let base = self.alphabet.len() as u64;
+        let mut digits = Vec::new();
+        loop {
+            digits.push(self.alphabet[(value % base) as usize]);
+            value /= base;
+            if value == 0 {
+                break;
+            }
+        }
+        digits.iter().rev().collect()
+    }
+}
+
+#[async_trait]
+impl CodeGenerator for SequentialGenerator {
+    async fn next_code(&self) -> Result<String, RepositoryError> {
+        let value = self.repository.next_sequence(self.start).await?;
+        Ok(self.encode(value))
+    }
+}
+
+pub fn from_config(config: &ShortenerConfig, repository: Arc<dyn UrlRepository>) -> Box<dyn CodeGenerator> {
+    let alphabet: Vec<char> = config.code_alphabet.chars().collect();
+    match config.code_strategy {
+        CodeStrategy::Nanoid => Box::new(NanoidGenerator::new(config.code_length, alphabet)),
+        CodeStrategy::Sequential => {
+            Box::new(SequentialGenerator::new(repository, alphabet, config.code_sequence_start))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repositories::memory_repository::InMemoryUrlRepository;
+
+    const BASE62: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+    fn sequential(start: u64) -> SequentialGenerator {
+        SequentialGenerator::new(Arc::new(InMemoryUrlRepository::new()), BASE62.chars().collect(), start)
+    }
+
+    #[actix_web::test]
+    async fn nanoid_codes_use_the_configured_length_and_alphabet() {
+        let generator = NanoidGenerator::new(8, "xyz".chars().collect());
+        for _ in 0..20 {
+            let code = generator.next_code().await.unwrap();
+            assert_eq!(code.len(), 8);
+            assert!(code.chars().all(|c| "xyz".contains(c)), "{}", code);
+        }
+    }
+
+    #[test]
+    fn sequential_codes_are_base62_with_the_most_significant_digit_first() {
+        let generator = sequential(0);
+        assert_eq!(generator.encode(0), "0");
+        assert_eq!(generator.encode(61), "z");
+        assert_eq!(generator.encode(62), "10");
+        assert_eq!(generator.encode(62 * 62 * 62), "1000");
+        assert_eq!(generator.encode(u64::MAX), "LygHa16AHYF");
+    }
+
+    #[actix_web::test]
+    async fn sequential_codes_count_up_from_the_start() {
+        let generator = sequential(61);
+        assert_eq!(generator.next_code().await.unwrap(), "z");
+        assert_eq!(generator.next_code().await.unwrap(), "10");
+        assert_eq!(generator.next_code().await.unwrap(), "11");
+    }
+}
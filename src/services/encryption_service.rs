@@ -1,9 +1,17 @@
+use std::collections::BTreeMap;
 use std::fs;
-use std::path::Path;
-use ring::{aead::{self, LessSafeKey, UnboundKey, AES_256_GCM, Nonce, NONCE_LEN}, rand::{SecureRandom, SystemRandom}};
-use base64::{engine::general_purpose::STANDARD, Engine};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use ring::{aead::{self, LessSafeKey, UnboundKey, AES_256_GCM, CHACHA20_POLY1305, Nonce, NONCE_LEN}, digest, hmac, rand::{SecureRandom, SystemRandom}};
+use base64::{engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD}, Engine};
+use crate::config::{EncryptionAlgorithm, SecurityConfig};
 
 const KEY_FILE_PATH: &str = "encryption.key";
+const KEY_DIR: &str = "keys";
+const KEY_FILE_EXTENSION: &str = "key";
+const FINGERPRINT_CONTEXT: &[u8] = b"url-fingerprint";
+const KEY_ENV_VAR: &str = "ENCRYPTION_KEY";
+const TOKEN_BYTES: usize = 32;
 
 #[derive(Debug)]
 pub enum EncryptionError {
@@ -26,57 +34,226 @@ impl std::fmt::Display for EncryptionError {
 
 impl std::error::Error for EncryptionError {}
 
+/// Raw key bytes together with the AEAD algorithm they belong to.
+struct KeyMaterial {
+    algorithm: EncryptionAlgorithm,
+    bytes: Vec<u8>,
+}
+
+struct KeyRing {
+    keys: BTreeMap<u8, LessSafeKey>,
+    active: u8,
+}
+
 pub struct EncryptionService {
-    key: LessSafeKey,
+    keyring: RwLock<KeyRing>,
+    fingerprint_key: hmac::Key,
+    algorithm: EncryptionAlgorithm,
+    /// Keys from `ENCRYPTION_KEY` or a configured key file are never written back, so they can't rotate.
+    external_key: bool,
     rng: SystemRandom,
 }
 
+fn aead_algorithm(algorithm: EncryptionAlgorithm) -> &'static aead::Algorithm {
+    match algorithm {
+        EncryptionAlgorithm::Aes256Gcm => &AES_256_GCM,
+        EncryptionAlgorithm::ChaCha20Poly1305 => &CHACHA20_POLY1305,
+    }
+}
+
 impl EncryptionService {
-    pub fn new() -> Result<Self, EncryptionError> {
+    /// Loads the key ring, encrypting new data with the configured algorithm. Every key
+    /// version records its own algorithm, so switching algorithms starts a new version
+    /// and older ciphertext stays readable.
+    ///
+    /// A key in `ENCRYPTION_KEY`, or else in the configured key file, is used as the only
+    /// key version; otherwise keys are loaded from (or generated into) `keys/`.
+    pub fn new(config: &SecurityConfig) -> Result<Self, EncryptionError> {
+        let algorithm = config.encryption_algorithm;
         let rng = SystemRandom::new();
-        let key_bytes = Self::load_or_generate_key(&rng)?;
-        
-        let unbound_key = UnboundKey::new(&AES_256_GCM, &key_bytes)
-            .map_err(|_| EncryptionError::KeyGeneration("Failed to create encryption key".to_string()))?;
-        
-        Ok(Self {
-            key: LessSafeKey::new(unbound_key),
+        let (key_material, external_key) = match Self::load_external_key(config)? {
+            Some(material) => (BTreeMap::from([(0, material)]), true),
+            None => (Self::load_or_generate_keys(&rng, algorithm)?, false),
+        };
+
+        let mut keys = BTreeMap::new();
+        for (version, material) in &key_material {
+            keys.insert(*version, Self::build_key(material)?);
+        }
+
+        // Fingerprints must stay stable across rotations, so they always derive from the oldest key
+        let (_, base_key) = key_material
+            .iter()
+            .next()
+            .ok_or_else(|| EncryptionError::KeyStorage("No encryption keys available".to_string()))?;
+        let derived = hmac::sign(&hmac::Key::new(hmac::HMAC_SHA256, &base_key.bytes), FINGERPRINT_CONTEXT);
+        let (active, active_algorithm) = key_material
+            .iter()
+            .next_back()
+            .map(|(version, material)| (*version, material.algorithm))
+            .unwrap_or((0, algorithm));
+
+        let service = Self {
+            keyring: RwLock::new(KeyRing { keys, active }),
+            fingerprint_key: hmac::Key::new(hmac::HMAC_SHA256, derived.as_ref()),
+            algorithm,
+            external_key,
             rng,
-        })
-    }
-
-    fn load_or_generate_key(rng: &SystemRandom) -> Result<[u8; 32], EncryptionError> {
-        if Path::new(KEY_FILE_PATH).exists() {
-            // Load existing key
-            let encoded_key = fs::read_to_string(KEY_FILE_PATH)
-                .map_err(|e| EncryptionError::KeyStorage(format!("Failed to read key file: {}", e)))?;
-            
-            let key_bytes = STANDARD.decode(encoded_key)
-                .map_err(|e| EncryptionError::KeyStorage(format!("Failed to decode key: {}", e)))?;
-            
-            if key_bytes.len() != 32 {
-                return Err(EncryptionError::KeyStorage("Invalid key length".to_string()));
+        };
+        if active_algorithm != algorithm {
+            if external_key {
+                return Err(EncryptionError::KeyStorage(format!(
+                    "Supplied key is for {}, but {} is configured",
+                    active_algorithm.as_str(), algorithm.as_str()
+                )));
             }
-            
-            let mut key = [0u8; 32];
-            key.copy_from_slice(&key_bytes);
-            Ok(key)
-        } else {
-            // Generate new key
-            let mut key = [0u8; 32];
-            rng.fill(&mut key)
-                .map_err(|_| EncryptionError::KeyGeneration("Failed to generate encryption key".to_string()))?;
-            
-            // Store the key
-            let encoded_key = STANDARD.encode(key);
-            fs::write(KEY_FILE_PATH, encoded_key)
-                .map_err(|e| EncryptionError::KeyStorage(format!("Failed to write key file: {}", e)))?;
-            
-            Ok(key)
+            service.rotate_key()?;
+        }
+
+        Ok(service)
+    }
+
+    fn build_key(material: &KeyMaterial) -> Result<LessSafeKey, EncryptionError> {
+        let unbound_key = UnboundKey::new(aead_algorithm(material.algorithm), &material.bytes)
+            .map_err(|_| EncryptionError::KeyGeneration("Failed to create encryption key".to_string()))?;
+
+        Ok(LessSafeKey::new(unbound_key))
+    }
+
+    fn key_path(version: u8) -> PathBuf {
+        Path::new(KEY_DIR).join(format!("{}.{}", version, KEY_FILE_EXTENSION))
+    }
+
+    fn load_external_key(config: &SecurityConfig) -> Result<Option<KeyMaterial>, EncryptionError> {
+        if let Ok(encoded) = std::env::var(KEY_ENV_VAR) {
+            return Self::parse_key(&encoded, config.encryption_algorithm, KEY_ENV_VAR).map(Some);
+        }
+
+        match &config.encryption_key_file {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .map_err(|e| EncryptionError::KeyStorage(format!("Failed to read key file {}: {}", path, e)))?;
+                Self::parse_key(&contents, config.encryption_algorithm, path).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn load_or_generate_keys(rng: &SystemRandom, algorithm: EncryptionAlgorithm) -> Result<BTreeMap<u8, KeyMaterial>, EncryptionError> {
+        fs::create_dir_all(KEY_DIR)
+            .map_err(|e| EncryptionError::KeyStorage(format!("Failed to create key directory: {}", e)))?;
+
+        let mut keys = BTreeMap::new();
+        let entries = fs::read_dir(KEY_DIR)
+            .map_err(|e| EncryptionError::KeyStorage(format!("Failed to read key directory: {}", e)))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| EncryptionError::KeyStorage(format!("Failed to read key directory: {}", e)))?
+                .path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some(KEY_FILE_EXTENSION) {
+                continue;
+            }
+
+            let version = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u8>().ok())
+                .ok_or_else(|| EncryptionError::KeyStorage(format!("Invalid key file name: {}", path.display())))?;
+            keys.insert(version, Self::read_key(&path)?);
+        }
+
+        if keys.is_empty() {
+            let key = if Path::new(KEY_FILE_PATH).exists() {
+                // Migrate the legacy single key file to version 0
+                Self::read_key(Path::new(KEY_FILE_PATH))?
+            } else {
+                Self::generate_key(rng, algorithm)?
+            };
+
+            Self::write_key(&Self::key_path(0), &key)?;
+            keys.insert(0, key);
+        }
+
+        Ok(keys)
+    }
+
+    /// Key files hold `<algorithm>:<base64 key>`; files with a bare base64 key
+    /// predate algorithm selection and are AES-256-GCM.
+    fn read_key(path: &Path) -> Result<KeyMaterial, EncryptionError> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| EncryptionError::KeyStorage(format!("Failed to read key file: {}", e)))?;
+        Self::parse_key(&contents, EncryptionAlgorithm::Aes256Gcm, &path.display().to_string())
+    }
+
+    /// Parses `<algorithm>:<base64 key>` or a bare base64 key, which is taken to be for `default_algorithm`.
+    fn parse_key(contents: &str, default_algorithm: EncryptionAlgorithm, source: &str) -> Result<KeyMaterial, EncryptionError> {
+        let (algorithm, encoded_key) = match contents.trim().split_once(':') {
+            Some((algorithm, encoded_key)) => (
+                algorithm.parse::<EncryptionAlgorithm>().map_err(EncryptionError::KeyStorage)?,
+                encoded_key,
+            ),
+            None => (default_algorithm, contents.trim()),
+        };
+        
+        let bytes = STANDARD.decode(encoded_key)
+            .map_err(|e| EncryptionError::KeyStorage(format!("Failed to decode key from {}: {}", source, e)))?;
+        
+        if bytes.len() != aead_algorithm(algorithm).key_len() {
+            return Err(EncryptionError::KeyStorage(format!(
+                "Invalid key length for {} in {}", algorithm.as_str(), source
+            )));
         }
+        
+        Ok(KeyMaterial { algorithm, bytes })
+    }
+
+    fn write_key(path: &Path, key: &KeyMaterial) -> Result<(), EncryptionError> {
+        let contents = format!("{}:{}", key.algorithm.as_str(), STANDARD.encode(&key.bytes));
+        fs::write(path, contents)
+            .map_err(|e| EncryptionError::KeyStorage(format!("Failed to write key file: {}", e)))
+    }
+
+    fn generate_key(rng: &SystemRandom, algorithm: EncryptionAlgorithm) -> Result<KeyMaterial, EncryptionError> {
+        let mut bytes = vec![0u8; aead_algorithm(algorithm).key_len()];
+        rng.fill(&mut bytes)
+            .map_err(|_| EncryptionError::KeyGeneration("Failed to generate encryption key".to_string()))?;
+        Ok(KeyMaterial { algorithm, bytes })
+    }
+
+    /// Generates a new key version used for all future encryption.
+    /// Older versions are kept so existing data still decrypts.
+    pub fn rotate_key(&self) -> Result<u8, EncryptionError> {
+        if self.external_key {
+            return Err(EncryptionError::KeyStorage(
+                "Keys supplied via ENCRYPTION_KEY or a key file cannot be rotated".to_string()
+            ));
+        }
+
+        let mut keyring = self.keyring
+            .write()
+            .map_err(|_| EncryptionError::KeyStorage("Key ring lock poisoned".to_string()))?;
+
+        let version = keyring.active
+            .checked_add(1)
+            .ok_or_else(|| EncryptionError::KeyGeneration("Key version space exhausted".to_string()))?;
+
+        let material = Self::generate_key(&self.rng, self.algorithm)?;
+        let key = Self::build_key(&material)?;
+        Self::write_key(&Self::key_path(version), &material)?;
+
+        keyring.keys.insert(version, key);
+        keyring.active = version;
+        Ok(version)
     }
 
     pub fn encrypt(&self, data: &str) -> Result<Vec<u8>, EncryptionError> {
+        let keyring = self.keyring
+            .read()
+            .map_err(|_| EncryptionError::Encryption("Key ring lock poisoned".to_string()))?;
+        let key = keyring.keys
+            .get(&keyring.active)
+            .ok_or_else(|| EncryptionError::Encryption("Active key missing".to_string()))?;
+
         let mut nonce_bytes = [0u8; NONCE_LEN];
         self.rng.fill(&mut nonce_bytes)
             .map_err(|_| EncryptionError::Encryption("Failed to generate nonce".to_string()))?;
@@ -84,12 +261,13 @@ impl EncryptionService {
         let nonce = Nonce::assume_unique_for_key(nonce_bytes);
         let mut in_out = data.as_bytes().to_vec();
         
-        self.key
+        key
             .seal_in_place_append_tag(nonce, aead::Aad::empty(), &mut in_out)
             .map_err(|_| EncryptionError::Encryption("Failed to encrypt data".to_string()))?;
 
-        // Prepend nonce to encrypted data
-        let mut result = Vec::with_capacity(NONCE_LEN + in_out.len());
+        // Prepend key version and nonce to encrypted data
+        let mut result = Vec::with_capacity(1 + NONCE_LEN + in_out.len());
+        result.push(keyring.active);
         result.extend_from_slice(&nonce_bytes);
         result.extend_from_slice(&in_out);
         
@@ -97,6 +275,27 @@ impl EncryptionService {
     }
 
     pub fn decrypt(&self, encrypted_data: &[u8]) -> Result<String, EncryptionError> {
+        let keyring = self.keyring
+            .read()
+            .map_err(|_| EncryptionError::Decryption("Key ring lock poisoned".to_string()))?;
+
+        // Versioned ciphertext: [version][nonce][ciphertext + tag]
+        if let Some((version, rest)) = encrypted_data.split_first() {
+            if let Some(key) = keyring.keys.get(version) {
+                if let Ok(plaintext) = Self::open(key, rest) {
+                    return Ok(plaintext);
+                }
+            }
+        }
+
+        // Data written before key versioning has no prefix and belongs to version 0
+        let legacy_key = keyring.keys
+            .get(&0)
+            .ok_or_else(|| EncryptionError::Decryption("Unknown key version".to_string()))?;
+        Self::open(legacy_key, encrypted_data)
+    }
+
+    fn open(key: &LessSafeKey, encrypted_data: &[u8]) -> Result<String, EncryptionError> {
         if encrypted_data.len() < NONCE_LEN {
             return Err(EncryptionError::Decryption("Invalid encrypted data".to_string()));
         }
@@ -105,11 +304,31 @@ impl EncryptionService {
         let nonce = Nonce::assume_unique_for_key(nonce_bytes.try_into().unwrap());
         
         let mut buffer = ciphertext.to_vec();
-        let plaintext = self.key
+        let plaintext = key
             .open_in_place(nonce, aead::Aad::empty(), &mut buffer)
             .map_err(|_| EncryptionError::Decryption("Failed to decrypt data".to_string()))?;
 
         String::from_utf8(plaintext.to_vec())
             .map_err(|_| EncryptionError::Decryption("Invalid UTF-8 in decrypted data".to_string()))
     }
-} 
\ No newline at end of file
+
+    /// Deterministic keyed hash of `data`, used to index URLs without storing them in plaintext.
+    pub fn fingerprint(&self, data: &str) -> Vec<u8> {
+        hmac::sign(&self.fingerprint_key, data.as_bytes()).as_ref().to_vec()
+    }
+
+    /// Random URL-safe secret handed out once when a link is created.
+    pub fn generate_token(&self) -> Result<String, EncryptionError> {
+        let mut bytes = [0u8; TOKEN_BYTES];
+        self.rng
+            .fill(&mut bytes)
+            .map_err(|_| EncryptionError::KeyGeneration("Failed to generate management token".to_string()))?;
+        Ok(URL_SAFE_NO_PAD.encode(bytes))
+    }
+
+    /// Hash stored in place of a token. Tokens are random, so an unkeyed SHA-256 is enough
+    /// and stays valid across key rotations.
+    pub fn token_hash(token: &str) -> String {
+        STANDARD.encode(digest::digest(&digest::SHA256, token.as_bytes()))
+    }
+}
@@ -1,2 +1,6 @@
 pub mod url_service;
-pub mod encryption_service; 
\ No newline at end of file
+pub mod encryption_service;
+pub mod qr_service;
+pub mod code_generator;
+#[cfg(test)]
+pub mod test_support;
@@ -0,0 +1,70 @@
+use std::io::Cursor;
+use image::{ImageFormat, Luma};
+use qrcode::{render::svg, QrCode};
+
+const MIN_DIMENSION: u32 = 256;
+
+#[derive(Debug)]
+pub enum QrError {
+    Encoding(String),
+    Rendering(String),
+}
+
+impl std::fmt::Display for QrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Encoding(msg) => write!(f, "QR encoding error: {}", msg),
+            Self::Rendering(msg) => write!(f, "QR rendering error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for QrError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QrFormat {
+    Png,
+    Svg,
+}
+
+impl QrFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_ascii_lowercase().as_str() {
+            "png" => Some(Self::Png),
+            "svg" => Some(Self::Svg),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Svg => "image/svg+xml",
+        }
+    }
+}
+
+pub fn render_qr(data: &str, format: QrFormat) -> Result<Vec<u8>, QrError> {
+    let code = QrCode::new(data.as_bytes())
+        .map_err(|e| QrError::Encoding(e.to_string()))?;
+
+    match format {
+        QrFormat::Png => {
+            let image = code
+                .render::<Luma<u8>>()
+                .min_dimensions(MIN_DIMENSION, MIN_DIMENSION)
+                .build();
+
+            let mut bytes = Vec::new();
+            image
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Png)
+                .map_err(|e| QrError::Rendering(e.to_string()))?;
+            Ok(bytes)
+        }
+        QrFormat::Svg => Ok(code
+            .render::<svg::Color>()
+            .min_dimensions(MIN_DIMENSION, MIN_DIMENSION)
+            .build()
+            .into_bytes()),
+    }
+}
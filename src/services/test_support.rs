@@ -0,0 +1,28 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::sync::{Arc, OnceLock};
+use crate::config::Config;
+use crate::repositories::memory_repository::InMemoryUrlRepository;
+use crate::repositories::url_repository::UrlRepository;
+use crate::services::url_service::UrlService;
+
+/// Defaults only (no env overrides), with a fixed key file so tests never touch `keys/`.
+pub fn test_config() -> Config {
+    static KEY_FILE: OnceLock<String> = OnceLock::new();
+    let key_file = KEY_FILE.get_or_init(|| {
+        let path = std::env::temp_dir().join(format!("url-service-test-{}.key", std::process::id()));
+        std::fs::write(&path, STANDARD.encode([7u8; 32])).expect("write test key");
+        path.display().to_string()
+    });
+    let mut config: Config = toml::from_str("").expect("default config");
+    config.security.encryption_key_file = Some(key_file.clone());
+    config
+}
+
+pub fn service_with(repository: Arc<dyn UrlRepository>, config: Config) -> UrlService {
+    UrlService::new(repository, Arc::new(config)).expect("build service")
+}
+
+pub fn test_service() -> UrlService {
+    service_with(Arc::new(InMemoryUrlRepository::new()), test_config())
+}
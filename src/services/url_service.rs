@@ -1,13 +1,22 @@
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 use std::fmt;
 use url::Url;
 use base64::engine::general_purpose::STANDARD;
 use base64::Engine;
-use tracing::debug;
+use ring::constant_time::verify_slices_are_equal;
+use ring::hmac;
+use serde::Serialize;
+use futures::StreamExt;
+use tracing::{debug, error, warn, Instrument};
 use crate::{
     config::Config,
-    repositories::url_repository::{SledUrlRepository, RepositoryError},
+    repositories::url_repository::{Entry, EntryStream, UrlRepository, RepositoryError},
+    repositories::url_record::{UrlRecord, day_number, format_day, now_millis},
     services::encryption_service::{EncryptionService, EncryptionError},
+    services::code_generator::{self, CodeGenerator},
 };
 
 #[derive(Debug)]
@@ -18,17 +27,41 @@ pub enum UrlServiceError {
     InvalidAlias(String),
     AliasExists(String),
     EncryptionError(EncryptionError),
+    Expired(String),
+    DomainNotAllowed(String),
+    CodeGenerationFailed(usize),
+    Unreachable(String),
+    InvalidManageToken(String),
+    InvalidDomain(String),
+    Deleted(String),
+    NotDeleted(String),
+    SelfReference(String),
+    IdempotencyKeyReused(String),
+    InvalidDescription(String),
+    InvalidTags(String),
 }
 
 impl std::fmt::Display for UrlServiceError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Self::InvalidUrl(url) => write!(f, "Invalid URL format: {}", url),
+            Self::InvalidUrl(msg) => write!(f, "Invalid URL: {}", msg),
             Self::NotFound(code) => write!(f, "URL not found: {}", code),
             Self::StorageError(e) => write!(f, "Storage error: {}", e),
             Self::InvalidAlias(msg) => write!(f, "Invalid alias: {}", msg),
             Self::AliasExists(alias) => write!(f, "Alias already exists: {}", alias),
             Self::EncryptionError(e) => write!(f, "Encryption error: {}", e),
+            Self::Expired(code) => write!(f, "URL has expired: {}", code),
+            Self::DomainNotAllowed(host) => write!(f, "Domain not allowed: {}", host),
+            Self::CodeGenerationFailed(attempts) => write!(f, "Failed to generate a unique short code after {} attempts", attempts),
+            Self::Unreachable(msg) => write!(f, "Destination unreachable: {}", msg),
+            Self::InvalidManageToken(code) => write!(f, "Missing or invalid management token for: {}", code),
+            Self::InvalidDomain(domain) => write!(f, "Not a configured short link domain: {}", domain),
+            Self::Deleted(code) => write!(f, "URL has been deleted: {}", code),
+            Self::NotDeleted(code) => write!(f, "URL is not deleted: {}", code),
+            Self::SelfReference(url) => write!(f, "URL points back at this shortener: {}", url),
+            Self::IdempotencyKeyReused(key) => write!(f, "Idempotency key reused with a different request: {}", key),
+            Self::InvalidDescription(msg) => write!(f, "Invalid description: {}", msg),
+            Self::InvalidTags(msg) => write!(f, "Invalid tags: {}", msg),
         }
     }
 }
@@ -60,81 +93,966 @@ pub struct ShortenedUrl {
     pub short_code: String,
     pub original_url: String,
     pub full_short_url: String,
+    pub expires_at: Option<u64>,
+    pub created_at: Option<u64>,
+    pub one_time: bool,
+    /// `false` when an existing mapping was returned instead of a new one being stored.
+    pub created: bool,
+    /// Plaintext management token, only present right after the link was created.
+    pub manage_token: Option<String>,
 }
 
+#[derive(Debug, Clone)]
+pub struct UrlSummary {
+    pub short_code: String,
+    pub original_url: String,
+    pub clicks: u64,
+    pub expires_at: Option<u64>,
+    pub created_at: Option<u64>,
+    /// `None` until the link is first resolved.
+    pub last_accessed: Option<u64>,
+    pub one_time: bool,
+    pub max_clicks: Option<u64>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    /// Distinct hashed client IPs; only counted by `get_stats`, and `None` in privacy mode.
+    pub unique_visitors: Option<u64>,
+    /// Clicks by referrer host and by user-agent category; only filled in by `get_stats`
+    /// while analytics are enabled.
+    pub referrers: Option<BTreeMap<String, u64>>,
+    pub user_agents: Option<BTreeMap<String, u64>>,
+}
+
+/// Request details a redirect may record for analytics.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ClickContext<'a> {
+    pub client_ip: Option<&'a str>,
+    pub referrer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+/// A destination resolved for a redirect, with the link properties that decide whether
+/// the redirect may be cached.
+#[derive(Debug, Clone)]
+pub struct ResolvedUrl {
+    pub url: String,
+    pub one_time: bool,
+    pub max_clicks: Option<u64>,
+    pub expires_at: Option<u64>,
+}
+
+impl ResolvedUrl {
+    fn new(url: String, record: &UrlRecord) -> Self {
+        Self { url, one_time: record.one_time, max_clicks: record.max_clicks, expires_at: record.expires_at }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DailyClicks {
+    /// UTC date as `yyyy-mm-dd`.
+    pub date: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct UrlPage {
+    pub total: usize,
+    pub urls: Vec<UrlSummary>,
+}
+
+/// A mapping recreated from a backup; the destination is plaintext and re-encrypted on import.
+#[derive(Debug, Clone)]
+pub struct ImportEntry {
+    pub short_code: String,
+    pub original_url: String,
+    pub expires_at: Option<u64>,
+    pub created_at: Option<u64>,
+    pub one_time: bool,
+    pub max_clicks: Option<u64>,
+    /// Restored as the link's click count, so `max_clicks` keeps counting from the backup.
+    pub clicks: u64,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportOutcome {
+    Created,
+    Skipped,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ShortenOptions {
+    pub custom_alias: Option<String>,
+    pub expires_in_seconds: Option<u64>,
+    pub one_time: bool,
+    pub max_clicks: Option<u64>,
+    /// Probe the destination with a HEAD request before storing it.
+    pub verify_reachable: bool,
+    /// Vanity domain to publish the short URL under; any domain resolves any code.
+    pub domain: Option<String>,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+}
+
+/// First response for an `Idempotency-Key`, replayed for retries of the same request.
+struct IdempotentResponse {
+    request: String,
+    expires_at: u64,
+    response: ShortenedUrl,
+}
+
+/// Body of the creation webhook: the shorten response without the management token,
+/// which is a secret for the link's creator alone.
+#[derive(Serialize)]
+struct CreationEvent<'a> {
+    short_code: &'a str,
+    original_url: &'a str,
+    short_url: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    created_at: Option<u64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    one_time: bool,
+}
+
+pub const MAX_PAGE_SIZE: usize = 100;
+/// Longest link description accepted, in characters.
+pub const MAX_DESCRIPTION_LENGTH: usize = 500;
+/// Bounds on a link's tags, which are stored twice: in the record and in the tag index.
+pub const MAX_TAGS: usize = 10;
+pub const MAX_TAG_LENGTH: usize = 32;
+pub const MAX_TOP_LIMIT: usize = 100;
+const HEALTH_CHECK_KEY: &str = "__health_check__";
+const PURGE_BATCH_SIZE: usize = 1000;
+const MAX_CODE_ATTEMPTS: usize = 5;
+/// Marks a destination stored without encryption; `:` is outside the base64 alphabet, so
+/// ciphertext never starts with it.
+const PLAINTEXT_PREFIX: &str = "plain:";
+/// Delay before the first webhook retry; later retries wait proportionally longer.
+const WEBHOOK_RETRY_DELAY: Duration = Duration::from_millis(500);
+/// Bounds the idempotency cache; keys beyond it simply aren't remembered until others expire.
+const MAX_IDEMPOTENCY_KEYS: usize = 10_000;
+const MAX_PROBE_REDIRECTS: usize = 5;
+/// Upper bound on resolving a destination host for `block_private_hosts`.
+const HOST_RESOLVE_TIMEOUT: Duration = Duration::from_secs(2);
+/// Characters of HMAC checksum appended to generated codes when `signed_codes` is on.
+const CODE_CHECKSUM_LENGTH: usize = 4;
+const REFERRER_DIMENSION: &str = "referrer";
+const USER_AGENT_DIMENSION: &str = "user_agent";
+/// Longest a DNS name can be; anything longer is bucketed as unknown rather than stored.
+const MAX_REFERRER_HOST_LENGTH: usize = 253;
+const BOT_MARKERS: &[&str] = &["bot", "crawl", "spider", "slurp", "facebookexternalhit", "preview"];
+
 pub struct UrlService {
-    repository: Arc<SledUrlRepository>,
+    repository: Arc<dyn UrlRepository>,
     config: Arc<Config>,
     encryption: Arc<EncryptionService>,
+    encrypt_destinations: bool,
+    code_generator: Box<dyn CodeGenerator>,
+    code_length: usize,
+    code_alphabet: Vec<char>,
+    /// Signs generated codes; `None` unless `signed_codes` is on.
+    code_signing_key: Option<hmac::Key>,
+    reserved_codes: HashSet<String>,
+    allowed_domains: Vec<String>,
+    blocked_domains: Vec<String>,
+    allowed_schemes: Vec<String>,
+    block_private_hosts: bool,
+    /// Every base URL short links are served under; destinations below them would loop.
+    own_bases: Vec<Url>,
+    strip_fragments: bool,
+    dedup_enabled: bool,
+    max_url_length: usize,
+    fold_alias_case: bool,
+    allow_numeric_aliases: bool,
+    alias_min_length: usize,
+    alias_max_length: usize,
+    flush_on_write: bool,
+    analytics_enabled: bool,
+    delete_undo_window_ms: u64,
+    idempotency_window_ms: u64,
+    /// Kept in memory only: replayed responses include the plaintext management token,
+    /// which must never reach storage.
+    idempotency: Mutex<HashMap<String, IdempotentResponse>>,
+    /// Keys the client IP hash used for unique visitors; `None` in privacy mode.
+    visitor_key: Option<hmac::Key>,
+    http_client: reqwest::Client,
+    creation_webhook: Option<String>,
+    webhook_retries: u32,
+    webhook_client: reqwest::Client,
 }
 
 impl UrlService {
-    pub fn new(repository: Arc<SledUrlRepository>, config: Arc<Config>) -> Result<Self, UrlServiceError> {
-        let encryption = EncryptionService::new()
+    pub fn new(repository: Arc<dyn UrlRepository>, config: Arc<Config>) -> Result<Self, UrlServiceError> {
+        let encryption = EncryptionService::new(&config.security)
             .map_err(UrlServiceError::EncryptionError)?;
+        let http_client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.shortener.reachability_timeout_ms))
+            .redirect(reqwest::redirect::Policy::limited(MAX_PROBE_REDIRECTS))
+            .build()
+            .map_err(|e| UrlServiceError::Unreachable(format!("Failed to build HTTP client: {}", e)))?;
+        let webhook_client = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.webhook.timeout_ms))
+            .build()
+            .map_err(|e| UrlServiceError::Unreachable(format!("Failed to build webhook client: {}", e)))?;
         
         Ok(Self {
+            code_generator: code_generator::from_config(&config.shortener, Arc::clone(&repository)),
             repository,
+            code_length: config.shortener.code_length,
+            code_alphabet: config.shortener.code_alphabet.chars().collect(),
+            code_signing_key: config.security.code_signing_secret
+                .as_deref()
+                .filter(|_| config.shortener.signed_codes)
+                .map(|secret| hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes())),
+            reserved_codes: config.shortener.reserved_codes
+                .iter()
+                .map(|code| code.to_lowercase())
+                .collect(),
+            allowed_domains: config.shortener.allowed_domains
+                .iter()
+                .map(|domain| domain.to_lowercase())
+                .collect(),
+            blocked_domains: config.shortener.blocked_domains
+                .iter()
+                .map(|domain| domain.to_lowercase())
+                .collect(),
+            allowed_schemes: config.shortener.allowed_schemes
+                .iter()
+                .map(|scheme| scheme.to_lowercase())
+                .collect(),
+            block_private_hosts: config.shortener.block_private_hosts,
+            own_bases: [Some(config.public_url()), config.server_url()]
+                .into_iter()
+                .flatten()
+                .chain(config.server.vanity_domains.iter().map(|domain| config.vanity_url(domain)))
+                .filter_map(|base| Url::parse(&base).ok())
+                .collect(),
+            strip_fragments: config.shortener.strip_fragments,
+            dedup_enabled: config.shortener.dedup_enabled,
+            max_url_length: config.shortener.max_url_length,
+            fold_alias_case: config.shortener.case_insensitive_aliases,
+            allow_numeric_aliases: config.shortener.allow_numeric_aliases,
+            alias_min_length: config.shortener.alias_min_length,
+            alias_max_length: config.shortener.alias_max_length,
+            flush_on_write: config.storage.flush_on_write,
+            analytics_enabled: config.analytics.enabled,
+            delete_undo_window_ms: config.shortener.delete_undo_window_secs.saturating_mul(1000),
+            idempotency_window_ms: config.shortener.idempotency_window_secs.saturating_mul(1000),
+            idempotency: Mutex::new(HashMap::new()),
+            visitor_key: config.analytics.ip_salt
+                .as_deref()
+                .filter(|_| !config.analytics.privacy_mode)
+                .map(|salt| hmac::Key::new(hmac::HMAC_SHA256, salt.as_bytes())),
+            http_client,
+            encrypt_destinations: config.security.encryption_enabled,
+            creation_webhook: config.webhook.creation_url.clone(),
+            webhook_retries: config.webhook.retries,
+            webhook_client,
             config,
             encryption: Arc::new(encryption),
         })
     }
 
-    fn validate_alias(alias: &str) -> Result<(), UrlServiceError> {
-        if alias.len() < 3 {
-            return Err(UrlServiceError::InvalidAlias("Alias must be at least 3 characters long".to_string()));
+    pub fn full_short_url(&self, short_code: &str) -> String {
+        format!("{}/{}", self.config.public_url(), short_code)
+    }
+
+    /// Base URL links are published under: the requested vanity domain, or `public_url`.
+    fn base_url(&self, domain: Option<&str>) -> Result<String, UrlServiceError> {
+        let Some(domain) = domain else {
+            return Ok(self.config.public_url());
+        };
+        let domain = domain.trim().to_lowercase();
+        if !self.config.server.vanity_domains.contains(&domain) {
+            return Err(UrlServiceError::InvalidDomain(domain));
+        }
+        Ok(self.config.vanity_url(&domain))
+    }
+
+    /// Matches `host` against `domain` itself and any of its subdomains.
+    fn host_matches(host: &str, domain: &str) -> bool {
+        host == domain || host.strip_suffix(domain).is_some_and(|prefix| prefix.ends_with('.'))
+    }
+
+    /// Reduces a `Referer` to its host so analytics stay bounded; clicks without one are "direct".
+    fn referrer_bucket(referrer: Option<&str>) -> String {
+        let Some(referrer) = referrer else {
+            return "direct".to_string();
+        };
+        Url::parse(referrer)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_lowercase))
+            .filter(|host| host.len() <= MAX_REFERRER_HOST_LENGTH)
+            .unwrap_or_else(|| "unknown".to_string())
+    }
+
+    /// Coarse user-agent category: crawlers and link unfurlers are "bot", anything
+    /// browser-like is "browser", and the rest (CLI tools, libraries) is "other".
+    fn agent_category(user_agent: Option<&str>) -> &'static str {
+        let Some(user_agent) = user_agent.map(str::to_lowercase) else {
+            return "other";
+        };
+        if BOT_MARKERS.iter().any(|marker| user_agent.contains(marker)) {
+            "bot"
+        } else if user_agent.starts_with("mozilla/") {
+            "browser"
+        } else {
+            "other"
+        }
+    }
+
+    fn check_domain(&self, parsed_url: &Url) -> Result<(), UrlServiceError> {
+        let host = parsed_url.host_str().unwrap_or_default().to_lowercase();
+
+        if self.blocked_domains.iter().any(|domain| Self::host_matches(&host, domain)) {
+            return Err(UrlServiceError::DomainNotAllowed(host));
+        }
+        if !self.allowed_domains.is_empty()
+            && !self.allowed_domains.iter().any(|domain| Self::host_matches(&host, domain))
+        {
+            return Err(UrlServiceError::DomainNotAllowed(host));
         }
-        if alias.len() > 32 {
-            return Err(UrlServiceError::InvalidAlias("Alias must not exceed 32 characters".to_string()));
+
+        Ok(())
+    }
+
+    /// Claims a freshly generated code, regenerating on collision up to `MAX_CODE_ATTEMPTS` times.
+    /// Custom aliases live in the same keyspace and are claimed with the same create-only write,
+    /// so a generated code can never overwrite an alias that happens to look like one.
+    async fn insert_generated(&self, value: &str, url_hash: Option<&[u8]>) -> Result<String, UrlServiceError> {
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let short_code = self.generate_code().await?;
+            if self.repository.store_if_absent(&short_code, value, url_hash).await? {
+                return Ok(short_code);
+            }
+            debug!(short_code = %short_code, "Generated short code collided, retrying");
         }
-        if !alias.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+
+        Err(UrlServiceError::CodeGenerationFailed(MAX_CODE_ATTEMPTS))
+    }
+
+    /// Draws codes until one is not reserved, giving up after `MAX_CODE_ATTEMPTS` draws so a
+    /// reserved list covering the whole code space fails the request instead of spinning.
+    async fn generate_code(&self) -> Result<String, UrlServiceError> {
+        for _ in 0..MAX_CODE_ATTEMPTS {
+            let mut code = self.code_generator.next_code().await?;
+            if let Some(key) = &self.code_signing_key {
+                code.push_str(&self.code_checksum(key, &code));
+            }
+            if !self.is_reserved(&code) {
+                return Ok(code);
+            }
+        }
+
+        Err(UrlServiceError::CodeGenerationFailed(MAX_CODE_ATTEMPTS))
+    }
+
+    /// Maps the leading HMAC bytes onto the code alphabet. The modulo bias is irrelevant here;
+    /// the checksum only has to be infeasible to guess, not uniformly distributed.
+    fn code_checksum(&self, key: &hmac::Key, payload: &str) -> String {
+        hmac::sign(key, payload.as_bytes())
+            .as_ref()
+            .iter()
+            .take(CODE_CHECKSUM_LENGTH)
+            .map(|byte| self.code_alphabet[*byte as usize % self.code_alphabet.len()])
+            .collect()
+    }
+
+    /// Whether a code has the shape of a signed code but a checksum that doesn't match.
+    /// Codes of any other length (custom aliases, codes from before signing) are left alone.
+    fn is_forged(&self, short_code: &str) -> bool {
+        let Some(key) = &self.code_signing_key else {
+            return false;
+        };
+        if short_code.len() != self.code_length + CODE_CHECKSUM_LENGTH
+            || !short_code.is_char_boundary(self.code_length)
+        {
+            return false;
+        }
+        let (payload, checksum) = short_code.split_at(self.code_length);
+        verify_slices_are_equal(self.code_checksum(key, payload).as_bytes(), checksum.as_bytes()).is_err()
+    }
+
+    fn is_reserved(&self, code: &str) -> bool {
+        self.reserved_codes.contains(&code.to_lowercase())
+    }
+
+    /// Trims surrounding whitespace from a custom alias and lowercases it when
+    /// `case_insensitive_aliases` is on.
+    fn normalize_alias(&self, alias: &str) -> String {
+        let alias = alias.trim();
+        if self.fold_alias_case {
+            alias.to_lowercase()
+        } else {
+            alias.to_string()
+        }
+    }
+
+    /// Maps a requested code to the key it is stored under. With `case_insensitive_aliases`,
+    /// a code that doesn't exist as typed falls back to its lowercase form, where folded
+    /// aliases live. Generated codes are case-sensitive and always match exactly first.
+    /// Forged signed codes are reported as not found without touching storage.
+    async fn lookup_code(&self, short_code: &str) -> Result<String, UrlServiceError> {
+        if self.is_forged(short_code) {
+            return Err(UrlServiceError::NotFound(short_code.to_string()));
+        }
+        if !self.fold_alias_case
+            || !short_code.chars().any(char::is_uppercase)
+            || self.repository.get(short_code).await?.is_some()
+        {
+            return Ok(short_code.to_string());
+        }
+        Ok(short_code.to_lowercase())
+    }
+
+    fn validate_alias(&self, alias: &str) -> Result<(), UrlServiceError> {
+        if alias.trim().is_empty() {
+            return Err(UrlServiceError::InvalidAlias("Alias must not be empty or only whitespace".to_string()));
+        }
+        if alias.len() < self.alias_min_length {
+            return Err(UrlServiceError::InvalidAlias(format!("Alias must be at least {} characters long", self.alias_min_length)));
+        }
+        if alias.len() > self.alias_max_length {
+            return Err(UrlServiceError::InvalidAlias(format!("Alias must not exceed {} characters", self.alias_max_length)));
+        }
+        if !self.allow_numeric_aliases && alias.chars().all(|c| c.is_ascii_digit()) {
+            return Err(UrlServiceError::InvalidAlias("Alias must not consist only of digits".to_string()));
+        }
+        self.validate_code(alias)
+    }
+
+    /// The rules every stored code follows, chosen or generated. Imports check only these,
+    /// since generated codes may be shorter than `ALIAS_MIN_LEN` or made only of digits.
+    fn validate_code(&self, code: &str) -> Result<(), UrlServiceError> {
+        if code.is_empty() {
+            return Err(UrlServiceError::InvalidAlias("Code must not be empty".to_string()));
+        }
+        if !code.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
             return Err(UrlServiceError::InvalidAlias("Alias can only contain alphanumeric characters, hyphens, and underscores".to_string()));
         }
+        if self.is_reserved(code) {
+            return Err(UrlServiceError::InvalidAlias(format!("'{}' is a reserved word", code)));
+        }
+        // It would be rejected as forged on every lookup
+        if self.is_forged(code) {
+            return Err(UrlServiceError::InvalidAlias(format!(
+                "Alias must not be {} characters long while signed codes are enabled",
+                self.code_length + CODE_CHECKSUM_LENGTH
+            )));
+        }
+        Ok(())
+    }
+
+    /// Trims and lowercases tags, dropping duplicates. Tags share the alias alphabet so they
+    /// can't contain the tag index's separator.
+    fn normalize_tags(tags: Vec<String>) -> Result<Vec<String>, UrlServiceError> {
+        let mut normalized = Vec::with_capacity(tags.len());
+        for tag in tags {
+            normalized.push(Self::normalize_tag(&tag)?);
+        }
+        normalized.sort();
+        normalized.dedup();
+        if normalized.len() > MAX_TAGS {
+            return Err(UrlServiceError::InvalidTags(format!("A link can have at most {} tags", MAX_TAGS)));
+        }
+        Ok(normalized)
+    }
+
+    fn normalize_tag(tag: &str) -> Result<String, UrlServiceError> {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() {
+            return Err(UrlServiceError::InvalidTags("Tags must not be empty".to_string()));
+        }
+        if tag.chars().count() > MAX_TAG_LENGTH {
+            return Err(UrlServiceError::InvalidTags(format!("Tags must not exceed {} characters", MAX_TAG_LENGTH)));
+        }
+        if !tag.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err(UrlServiceError::InvalidTags("Tags can only contain alphanumeric characters, hyphens, and underscores".to_string()));
+        }
+        Ok(tag)
+    }
+
+    /// Sends a HEAD request to the destination. Connection failures, timeouts and 5xx
+    /// responses are rejected; 4xx is accepted since many sites refuse HEAD.
+    async fn verify_reachable(&self, url: &str, checked: &[SocketAddr]) -> Result<(), UrlServiceError> {
+        let client = match checked {
+            [] => self.http_client.clone(),
+            addresses => self.pinned_client(url, addresses)?,
+        };
+        let response = client
+            .head(url)
+            .send()
+            .await
+            .map_err(|e| UrlServiceError::Unreachable(e.without_url().to_string()))?;
+
+        if response.status().is_server_error() {
+            return Err(UrlServiceError::Unreachable(format!("server responded with {}", response.status())));
+        }
+        Ok(())
+    }
+
+    /// A probe client that connects only to the addresses `check_public_host` approved, so a
+    /// second DNS answer can't swap in an internal address, and that follows redirects only to
+    /// hosts known to be public: the pinned host itself or public IP literals. Any other hop
+    /// ends the probe, and its 3xx response counts as the answer.
+    fn pinned_client(&self, url: &str, addresses: &[SocketAddr]) -> Result<reqwest::Client, UrlServiceError> {
+        let host = Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(String::from))
+            .ok_or_else(|| UrlServiceError::InvalidUrl("Invalid URL format".to_string()))?;
+        let pinned_host = host.clone();
+        let policy = reqwest::redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_PROBE_REDIRECTS {
+                return attempt.error("too many redirects");
+            }
+            let public = match attempt.url().host() {
+                Some(url::Host::Ipv4(ip)) => !Self::is_internal(IpAddr::V4(ip)),
+                Some(url::Host::Ipv6(ip)) => !Self::is_internal(IpAddr::V6(ip)),
+                Some(url::Host::Domain(domain)) => domain == pinned_host,
+                None => false,
+            };
+            if public {
+                attempt.follow()
+            } else {
+                attempt.stop()
+            }
+        });
+
+        reqwest::Client::builder()
+            .timeout(Duration::from_millis(self.config.shortener.reachability_timeout_ms))
+            .redirect(policy)
+            .resolve_to_addrs(&host, addresses)
+            .build()
+            .map_err(|e| UrlServiceError::Unreachable(format!("Failed to build HTTP client: {}", e)))
+    }
+
+    /// Rejects destinations on private, loopback or link-local addresses when
+    /// `block_private_hosts` is on, so links can't point (or probe) into internal networks.
+    /// Hosts that don't resolve in time are rejected as well, failing closed.
+    ///
+    /// Returns the addresses that were checked, for the reachability probe to pin; empty when
+    /// the check is off.
+    async fn check_public_host(&self, url: &str) -> Result<Vec<SocketAddr>, UrlServiceError> {
+        if !self.block_private_hosts {
+            return Ok(Vec::new());
+        }
+        let parsed_url = Url::parse(url)
+            .map_err(|_| UrlServiceError::InvalidUrl("Invalid URL format".to_string()))?;
+        let Some(host) = parsed_url.host_str() else {
+            return Ok(Vec::new());
+        };
+
+        let port = parsed_url.port_or_known_default().unwrap_or(80);
+        let addresses: Vec<SocketAddr> = match parsed_url.host() {
+            Some(url::Host::Ipv4(ip)) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+            Some(url::Host::Ipv6(ip)) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+            _ => {
+                tokio::time::timeout(HOST_RESOLVE_TIMEOUT, tokio::net::lookup_host((host, port)))
+                    .await
+                    .map_err(|_| UrlServiceError::InvalidUrl(format!("Timed out resolving host '{}'", host)))?
+                    .map_err(|_| UrlServiceError::InvalidUrl(format!("Could not resolve host '{}'", host)))?
+                    .collect()
+            }
+        };
+
+        // Any internal address is enough, since the client may pick whichever it likes
+        if addresses.iter().any(|addr| Self::is_internal(addr.ip())) {
+            return Err(UrlServiceError::DomainNotAllowed(host.to_string()));
+        }
+        Ok(addresses)
+    }
+
+    fn is_internal(ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => {
+                ip.is_private()
+                    || ip.is_loopback()
+                    || ip.is_link_local()
+                    || ip.is_unspecified()
+                    || ip.is_broadcast()
+                    // Carrier-grade NAT (100.64.0.0/10) is internal to the provider
+                    || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+            }
+            IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+                Some(mapped) => Self::is_internal(IpAddr::V4(mapped)),
+                None => {
+                    ip.is_loopback()
+                        || ip.is_unspecified()
+                        || ip.is_unique_local()
+                        || ip.is_unicast_link_local()
+                }
+            },
+        }
+    }
+
+    /// Checks length, syntax and domain policy, returning the normalized URL.
+    ///
+    /// Parsing already lowercases the host and drops the scheme's default port; the
+    /// fragment is removed as well when `strip_fragments` is enabled. Dedup and
+    /// storage both operate on this normalized form.
+    fn validate_url(&self, url: &str) -> Result<String, UrlServiceError> {
+        // Length is checked in bytes before parsing so oversized input never reaches the parser
+        if url.len() > self.max_url_length {
+            return Err(UrlServiceError::InvalidUrl(format!(
+                "URL exceeds maximum length of {} bytes", self.max_url_length
+            )));
+        }
+
+        let mut parsed_url = Url::parse(url)
+            .map_err(|_| UrlServiceError::InvalidUrl("Invalid URL format".to_string()))?;
+        if self.strip_fragments {
+            parsed_url.set_fragment(None);
+        }
+
+        // Schemes like `javascript:` and `data:` would turn the redirect into an XSS vector
+        let scheme = parsed_url.scheme().to_lowercase();
+        if !self.allowed_schemes.contains(&scheme) {
+            return Err(UrlServiceError::InvalidUrl(format!("URL scheme '{}' is not allowed", scheme)));
+        }
+        self.check_domain(&parsed_url)?;
+        self.check_self_reference(&parsed_url)?;
+        Ok(parsed_url.to_string())
+    }
+
+    /// Rejects destinations served by this instance, such as another short link, which would
+    /// redirect back here and could loop. Hosts must match; ports only count when a base names
+    /// one explicitly, so `http` and `https` links to the public domain are both caught.
+    fn check_self_reference(&self, parsed_url: &Url) -> Result<(), UrlServiceError> {
+        let Some(host) = parsed_url.host_str() else {
+            return Ok(());
+        };
+        let loops = self.own_bases.iter().any(|base| {
+            let base_path = base.path().trim_end_matches('/');
+            base.host_str() == Some(host)
+                && base.port().is_none_or(|port| parsed_url.port_or_known_default() == Some(port))
+                && parsed_url.path().strip_prefix(base_path).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        if loops {
+            return Err(UrlServiceError::SelfReference(parsed_url.to_string()));
+        }
         Ok(())
     }
 
+    /// Encodes a destination or description for storage: base64 ciphertext, or the value
+    /// behind `PLAINTEXT_PREFIX` when encryption is disabled.
+    fn encrypt_value(&self, value: &str) -> Result<String, UrlServiceError> {
+        if !self.encrypt_destinations {
+            return Ok(format!("{}{}", PLAINTEXT_PREFIX, value));
+        }
+        Ok(STANDARD.encode(self.encryption.encrypt(value)?))
+    }
+
+    fn encrypt_description(&self, description: Option<&str>) -> Result<Option<String>, UrlServiceError> {
+        let Some(description) = description else {
+            return Ok(None);
+        };
+        if description.chars().count() > MAX_DESCRIPTION_LENGTH {
+            return Err(UrlServiceError::InvalidDescription(format!(
+                "Description must not exceed {} characters",
+                MAX_DESCRIPTION_LENGTH
+            )));
+        }
+        Ok(Some(self.encrypt_value(description)?))
+    }
+
+    /// Fingerprint of a stored value's destination, used to locate its index entry.
+    fn stored_fingerprint(&self, short_code: &str, raw: &str) -> Option<Vec<u8>> {
+        match UrlRecord::decode(raw)
+            .map_err(UrlServiceError::from)
+            .and_then(|record| self.decrypt_destination(&record))
+        {
+            Ok(url) => Some(self.encryption.fingerprint(&url)),
+            Err(e) => {
+                warn!(short_code = %short_code, error = %e, "Cannot locate index entry");
+                None
+            }
+        }
+    }
+
     async fn find_existing_url(&self, url: &str) -> Result<Option<String>, UrlServiceError> {
-        let encrypted_url = self.encryption.encrypt(url)?;
-        let encoded_url = STANDARD.encode(&encrypted_url);
-        
-        // Scan through existing entries to find a match
-        let iter = self.repository.scan_prefix("").await?;
-        for entry in iter {
+        // Ciphertext is nonce-randomized, so look up by the keyed fingerprint instead
+        let url_hash = self.encryption.fingerprint(url);
+        Ok(self.repository.find_by_hash(&url_hash).await?)
+    }
+
+    /// Indexes mappings created before the reverse index existed so they still deduplicate,
+    /// and schedules expiring ones written before the expiry schedule existed.
+    pub async fn backfill_indexes(&self) -> Result<usize, UrlServiceError> {
+        let mut indexed = 0;
+        for entry in self.repository.scan_prefix("").await? {
             let entry = entry?;
-            if entry.value == encoded_url.as_bytes() {
-                return Ok(Some(String::from_utf8_lossy(&entry.key).to_string()));
+            let short_code = String::from_utf8_lossy(&entry.key).into_owned();
+            let record = match UrlRecord::decode(&String::from_utf8_lossy(&entry.value)) {
+                Ok(record) => record,
+                Err(e) => {
+                    warn!(short_code = %short_code, error = %e, "Skipping unreadable entry during index backfill");
+                    continue;
+                }
+            };
+            if let Some(expires_at) = record.expires_at {
+                self.repository.schedule_expiry(&short_code, expires_at).await?;
+            }
+            if !record.is_indexable() {
+                continue;
+            }
+
+            let decrypted_url = match self.decrypt_destination(&record) {
+                Ok(url) => url,
+                Err(e) => {
+                    warn!(short_code = %short_code, error = %e, "Skipping unreadable entry during index backfill");
+                    continue;
+                }
+            };
+
+            if self.repository.index_if_absent(&self.encryption.fingerprint(&decrypted_url), &short_code).await? {
+                indexed += 1;
             }
         }
-        
-        Ok(None)
+
+        Ok(indexed)
+    }
+
+    fn decrypt_destination(&self, record: &UrlRecord) -> Result<String, UrlServiceError> {
+        self.decrypt_value(&record.destination)
     }
 
-    pub async fn shorten_url(&self, url: String, custom_alias: Option<String>) -> Result<ShortenedUrl, UrlServiceError> {
-        // Validate URL format
-        let parsed_url = Url::parse(&url)
-            .map_err(|_| UrlServiceError::InvalidUrl(url.clone()))?;
-        let normalized_url = parsed_url.to_string();
+    fn decrypt_description(&self, record: &UrlRecord) -> Result<Option<String>, UrlServiceError> {
+        record.description.as_deref().map(|description| self.decrypt_value(description)).transpose()
+    }
+
+    fn decrypt_value(&self, stored: &str) -> Result<String, UrlServiceError> {
+        // Checked regardless of the current setting, so toggling it keeps old links readable
+        if let Some(value) = stored.strip_prefix(PLAINTEXT_PREFIX) {
+            return Ok(value.to_string());
+        }
+        let encrypted_data = STANDARD.decode(stored)
+            .map_err(|_| UrlServiceError::StorageError(RepositoryError::Storage(
+                "Invalid base64 data".to_string()
+            )))?;
+
+        Ok(self.encryption.decrypt(&encrypted_data)?)
+    }
+
+    /// Shortens a single URL. With an idempotency key, a retry of the same request within
+    /// the idempotency window gets the first response back instead of creating another link.
+    pub async fn shorten_url(&self, url: String, options: ShortenOptions, idempotency_key: Option<&str>) -> Result<ShortenedUrl, UrlServiceError> {
+        let Some(key) = idempotency_key.filter(|_| self.idempotency_window_ms > 0) else {
+            let shortened = self.create_short_url(url, options, self.flush_on_write).await?;
+            self.notify_created(&shortened);
+            return Ok(shortened);
+        };
+
+        let request = format!("{:?} {:?}", url, options);
+        if let Some(response) = self.replay_idempotent(key, &request)? {
+            debug!(idempotency_key = %key, "Replaying shorten response");
+            return Ok(response);
+        }
+        let shortened = self.create_short_url(url, options, self.flush_on_write).await?;
+        self.remember_idempotent(key, request, &shortened);
+        self.notify_created(&shortened);
+        Ok(shortened)
+    }
+
+    /// Posts a newly created link to the creation webhook in the background. Existing links
+    /// returned by deduplication aren't announced again.
+    fn notify_created(&self, shortened: &ShortenedUrl) {
+        let Some(webhook_url) = self.creation_webhook.clone() else {
+            return;
+        };
+        if !shortened.created {
+            return;
+        }
+        let body = match serde_json::to_vec(&CreationEvent {
+            short_code: &shortened.short_code,
+            original_url: &shortened.original_url,
+            short_url: &shortened.full_short_url,
+            expires_at: shortened.expires_at,
+            created_at: shortened.created_at,
+            one_time: shortened.one_time,
+        }) {
+            Ok(body) => body,
+            Err(e) => {
+                error!(short_code = %shortened.short_code, error = %e, "Failed to encode creation webhook");
+                return;
+            }
+        };
+
+        let client = self.webhook_client.clone();
+        let retries = self.webhook_retries;
+        let short_code = shortened.short_code.clone();
+        tokio::spawn(
+            async move {
+                for attempt in 0..=retries {
+                    if attempt > 0 {
+                        tokio::time::sleep(WEBHOOK_RETRY_DELAY * attempt).await;
+                    }
+                    let result = client
+                        .post(&webhook_url)
+                        .header(reqwest::header::CONTENT_TYPE, "application/json")
+                        .body(body.clone())
+                        .send()
+                        .await;
+                    match result {
+                        Ok(response) if response.status().is_success() => return,
+                        Ok(response) => {
+                            warn!(short_code = %short_code, status = %response.status(), attempt, "Creation webhook rejected");
+                        }
+                        Err(e) => {
+                            warn!(short_code = %short_code, error = %e.without_url(), attempt, "Creation webhook failed");
+                        }
+                    }
+                }
+                error!(short_code = %short_code, attempts = retries + 1, "Giving up on creation webhook");
+            }
+            .in_current_span(),
+        );
+    }
+
+    fn replay_idempotent(&self, key: &str, request: &str) -> Result<Option<ShortenedUrl>, UrlServiceError> {
+        let cache = self.idempotency.lock().unwrap_or_else(PoisonError::into_inner);
+        match cache.get(key) {
+            Some(stored) if stored.expires_at > now_millis() => {
+                if stored.request != request {
+                    return Err(UrlServiceError::IdempotencyKeyReused(key.to_string()));
+                }
+                Ok(Some(stored.response.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn remember_idempotent(&self, key: &str, request: String, response: &ShortenedUrl) {
+        let now = now_millis();
+        let mut cache = self.idempotency.lock().unwrap_or_else(PoisonError::into_inner);
+        if cache.len() >= MAX_IDEMPOTENCY_KEYS {
+            cache.retain(|_, stored| stored.expires_at > now);
+        }
+        if cache.len() >= MAX_IDEMPOTENCY_KEYS && !cache.contains_key(key) {
+            warn!(idempotency_key = %key, "Idempotency cache full, not remembering key");
+            return;
+        }
+        cache.insert(key.to_string(), IdempotentResponse {
+            request,
+            expires_at: now.saturating_add(self.idempotency_window_ms),
+            response: response.clone(),
+        });
+    }
+
+    /// Flushes after a single-link write unless `flush_on_write` is off, in which case the
+    /// write becomes durable with the next periodic flush.
+    async fn flush_write(&self) -> Result<(), UrlServiceError> {
+        if self.flush_on_write {
+            self.repository.flush().await?;
+        }
+        Ok(())
+    }
+
+    /// Shortens every URL, flushing storage once at the end rather than per entry.
+    pub async fn shorten_batch(&self, urls: Vec<String>) -> Result<Vec<Result<ShortenedUrl, UrlServiceError>>, UrlServiceError> {
+        let mut results = Vec::with_capacity(urls.len());
+        for url in urls {
+            results.push(self.create_short_url(url, ShortenOptions::default(), false).await);
+        }
+
+        self.repository.flush().await?;
+        for shortened in results.iter().flatten() {
+            self.notify_created(shortened);
+        }
+        Ok(results)
+    }
+
+    /// Recreates mappings from a backup, flushing storage once at the end. Existing codes
+    /// are left alone unless `overwrite` is set.
+    pub async fn import_urls(&self, entries: Vec<ImportEntry>, overwrite: bool) -> Result<Vec<Result<ImportOutcome, UrlServiceError>>, UrlServiceError> {
+        let mut results = Vec::with_capacity(entries.len());
+        for entry in entries {
+            results.push(self.import_url(entry, overwrite).await);
+        }
+
+        self.repository.flush().await?;
+        Ok(results)
+    }
+
+    async fn import_url(&self, entry: ImportEntry, overwrite: bool) -> Result<ImportOutcome, UrlServiceError> {
+        let ImportEntry { short_code, original_url, expires_at, created_at, one_time, max_clicks, clicks, description, tags } = entry;
+        self.validate_code(&short_code)?;
+        let normalized_url = self.validate_url(&original_url)?;
+        let record = UrlRecord {
+            expires_at,
+            created_at: Some(created_at.unwrap_or_else(now_millis)),
+            one_time,
+            max_clicks,
+            description: self.encrypt_description(description.as_deref())?,
+            tags: Self::normalize_tags(tags)?,
+            ..UrlRecord::new(self.encrypt_value(&normalized_url)?)
+        };
+        let value = record.encode()?;
+
+        if overwrite {
+            if let Some(existing) = self.repository.get(&short_code).await? {
+                let old_hash = self.stored_fingerprint(&short_code, &existing);
+                self.repository.remove(&short_code, old_hash.as_deref()).await?;
+            }
+            self.repository.store(&short_code, &value, None).await?;
+        } else if !self.repository.store_if_absent(&short_code, &value, None).await? {
+            return Ok(ImportOutcome::Skipped);
+        }
+
+        if clicks > 0 {
+            self.repository.set_clicks(&short_code, clicks).await?;
+        }
+        if let Some(expires_at) = record.expires_at {
+            self.repository.schedule_expiry(&short_code, expires_at).await?;
+        }
+        for tag in &record.tags {
+            self.repository.tag_url(tag, &short_code).await?;
+        }
+
+        // Imported codes only claim an index slot that no other mapping holds yet
+        if record.is_indexable() {
+            self.repository
+                .index_if_absent(&self.encryption.fingerprint(&normalized_url), &short_code)
+                .await?;
+        }
+
+        Ok(ImportOutcome::Created)
+    }
+
+    async fn create_short_url(&self, url: String, options: ShortenOptions, flush: bool) -> Result<ShortenedUrl, UrlServiceError> {
+        let ShortenOptions { custom_alias, expires_in_seconds, one_time, max_clicks, verify_reachable, domain, description, tags } = options;
+        let tags = Self::normalize_tags(tags)?;
+        let base_url = self.base_url(domain.as_deref())?;
+        let custom_alias = custom_alias.map(|alias| self.normalize_alias(&alias));
+        let expires_at = expires_in_seconds
+            .map(|seconds| now_millis().saturating_add(seconds.saturating_mul(1000)));
+
+        let normalized_url = self.validate_url(&url)?;
 
         // If a custom alias is provided, validate and use it
         if let Some(alias) = custom_alias.as_ref() {
-            Self::validate_alias(alias)?;
+            self.validate_alias(alias)?;
             
             // Check if alias is already taken
-            if let Some(existing_url) = self.repository.get(alias).await? {
-                let encrypted_data = STANDARD.decode(&existing_url)
-                    .map_err(|_| UrlServiceError::StorageError(RepositoryError::Storage(
-                        "Invalid base64 data".to_string()
-                    )))?;
-                
-                let decrypted_url = self.encryption.decrypt(&encrypted_data)?;
+            if let Some(existing) = self.repository.get(alias).await? {
+                let record = UrlRecord::decode(&existing)?;
+                let decrypted_url = self.decrypt_destination(&record)?;
                 
-                // If the alias points to the same URL, return it
-                if decrypted_url == normalized_url {
-                    debug!("URL already exists with requested alias: {}", alias);
+                // If the alias points to the same URL and still resolves, return it. Deleted,
+                // consumed or exhausted aliases stay taken until they are swept.
+                if decrypted_url == normalized_url && !self.is_dead(alias, &record, now_millis()).await? {
+                    debug!(short_code = %alias, "URL already exists with requested alias");
                     return Ok(ShortenedUrl {
                         short_code: alias.clone(),
                         original_url: normalized_url,
-                        full_short_url: format!("{}/{}", self.config.server_url(), alias),
+                        full_short_url: format!("{}/{}", base_url, alias),
+                        expires_at: record.expires_at,
+                        created_at: record.created_at,
+                        one_time: record.one_time,
+                        created: false,
+                        manage_token: None,
                     });
                 }
                 
@@ -142,46 +1060,1122 @@ impl UrlService {
             }
         }
 
-        // Check if URL already exists
-        if let Some(existing_code) = self.find_existing_url(&normalized_url).await? {
-            debug!("URL already exists with code: {}", existing_code);
-            return Ok(ShortenedUrl {
-                short_code: existing_code.clone(),
-                original_url: normalized_url,
-                full_short_url: format!("{}/{}", self.config.server_url(), existing_code),
-            });
+        let record = UrlRecord {
+            expires_at,
+            created_at: Some(now_millis()),
+            one_time,
+            max_clicks,
+            description: self.encrypt_description(description.as_deref())?,
+            tags,
+            ..UrlRecord::new(self.encrypt_value(&normalized_url)?)
+        };
+
+        // Check if URL already exists. Expiring, one-time and described links always get their
+        // own code, so they are neither deduplicated nor indexed.
+        let url_hash = record
+            .is_indexable()
+            .then(|| self.encryption.fingerprint(&normalized_url));
+        // With dedup disabled the index is still maintained, but never consulted here
+        if url_hash.is_some() && self.dedup_enabled {
+            if let Some(existing_code) = self.find_existing_url(&normalized_url).await? {
+                debug!(short_code = %existing_code, "URL already exists");
+                let created_at = match self.repository.get(&existing_code).await? {
+                    Some(raw) => UrlRecord::decode(&raw)?.created_at,
+                    None => None,
+                };
+                return Ok(ShortenedUrl {
+                    short_code: existing_code.clone(),
+                    original_url: normalized_url,
+                    full_short_url: format!("{}/{}", base_url, existing_code),
+                    expires_at: None,
+                    created_at,
+                    one_time: false,
+                    created: false,
+                    manage_token: None,
+                });
+            }
         }
 
-        // Generate new short code if no custom alias or URL doesn't exist
-        let short_code = custom_alias.unwrap_or_else(|| nanoid::nanoid!(6));
-        
-        // Encrypt URL before storing
-        let encrypted_url = self.encryption.encrypt(&normalized_url)?;
-        
-        // Store encrypted URL
-        self.repository
-            .store(&short_code, &STANDARD.encode(&encrypted_url))
-            .await?;
+        // Network checks come last, once every local check has passed and the link is
+        // known to be new
+        let checked_addresses = self.check_public_host(&normalized_url).await?;
+        if verify_reachable {
+            self.verify_reachable(&normalized_url, &checked_addresses).await?;
+        }
+
+        // Only the hash is stored; the token itself is returned once below
+        let manage_token = self.encryption.generate_token()?;
+        let record = UrlRecord {
+            manage_token_hash: Some(EncryptionService::token_hash(&manage_token)),
+            ..record
+        };
+        let value = record.encode()?;
+
+        // Store encrypted URL along with its reverse index entry, generating
+        // a new short code if no custom alias was requested
+        let short_code = match custom_alias {
+            Some(alias) => {
+                // The earlier existence check is only a fast path; a concurrent request may
+                // have claimed the alias since, so the write itself must be create-only
+                if !self.repository.store_if_absent(&alias, &value, url_hash.as_deref()).await? {
+                    return Err(UrlServiceError::AliasExists(alias));
+                }
+                alias
+            }
+            None => self.insert_generated(&value, url_hash.as_deref()).await?,
+        };
+        if let Some(expires_at) = record.expires_at {
+            self.repository.schedule_expiry(&short_code, expires_at).await?;
+        }
+        for tag in &record.tags {
+            self.repository.tag_url(tag, &short_code).await?;
+        }
+        if flush {
+            self.repository.flush().await?;
+        }
 
         Ok(ShortenedUrl {
             short_code: short_code.clone(),
             original_url: normalized_url,
-            full_short_url: format!("{}/{}", self.config.server_url(), short_code),
+            full_short_url: format!("{}/{}", base_url, short_code),
+            expires_at: record.expires_at,
+            created_at: record.created_at,
+            one_time: record.one_time,
+            created: true,
+            manage_token: Some(manage_token),
         })
     }
 
-    pub async fn get_url(&self, short_code: &str) -> Result<String, UrlServiceError> {
-        match self.repository.get(short_code).await {
-            Ok(Some(encrypted_url)) => {
-                let encrypted_data = STANDARD.decode(&encrypted_url)
-                    .map_err(|_| UrlServiceError::StorageError(RepositoryError::Storage(
-                        "Invalid base64 data".to_string()
-                    )))?;
-                
-                Ok(self.encryption.decrypt(&encrypted_data)?)
+    /// Loads a mapping that can still be resolved, returning its raw value and record.
+    async fn load_active(&self, short_code: &str) -> Result<(String, UrlRecord), UrlServiceError> {
+        let raw = self.repository
+            .get(short_code)
+            .await?
+            .ok_or_else(|| UrlServiceError::NotFound(short_code.to_string()))?;
+        let record = UrlRecord::decode(&raw)?;
+        if record.deleted_at.is_some() {
+            return Err(UrlServiceError::Deleted(short_code.to_string()));
+        }
+        if self.is_dead(short_code, &record, now_millis()).await? {
+            return Err(UrlServiceError::Expired(short_code.to_string()));
+        }
+
+        Ok((raw, record))
+    }
+
+    /// Whether a mapping can no longer resolve: past its TTL, consumed, deleted, or out of clicks.
+    async fn is_dead(&self, short_code: &str, record: &UrlRecord, now: u64) -> Result<bool, UrlServiceError> {
+        if record.is_expired(now) || record.deleted_at.is_some() {
+            return Ok(true);
+        }
+        match record.max_clicks {
+            Some(max_clicks) => Ok(self.repository.get_clicks(short_code).await? >= max_clicks),
+            None => Ok(false),
+        }
+    }
+
+    /// Resolves a short code for a redirect and counts the click. Links with `max_clicks`
+    /// claim their click atomically before resolving, so concurrent hits cannot overshoot the limit.
+    /// The client IP is only used, hashed, when privacy mode is off.
+    pub async fn resolve_click(&self, short_code: &str, context: &ClickContext<'_>) -> Result<ResolvedUrl, UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        let (raw, record) = self.load_active(short_code).await?;
+        let url = self.decrypt_destination(&record)?;
+        // Consumed before anything is counted, so requests that lose the race leave no trace
+        if record.one_time {
+            self.consume(short_code, &raw, &record).await?;
+        }
+
+        match record.max_clicks {
+            Some(max_clicks) => match self.repository.increment_clicks_within(short_code, max_clicks).await? {
+                None => return Err(UrlServiceError::Expired(short_code.to_string())),
+                // The last allowed click makes the link dead, so hand it to the cleanup sweep
+                Some(clicks) if clicks == max_clicks => {
+                    self.repository.schedule_expiry(short_code, now_millis()).await?;
+                }
+                Some(_) => {}
+            },
+            None => {
+                if let Err(e) = self.repository.increment_clicks(short_code).await {
+                    error!(short_code = %short_code, error = %e, "Failed to record click");
+                }
+            }
+        }
+
+        if let Err(e) = self.repository.increment_daily_clicks(short_code, day_number(now_millis())).await {
+            error!(short_code = %short_code, error = %e, "Failed to record daily clicks");
+        }
+        if self.analytics_enabled {
+            self.record_breakdown(short_code, REFERRER_DIMENSION, &Self::referrer_bucket(context.referrer)).await;
+            self.record_breakdown(short_code, USER_AGENT_DIMENSION, Self::agent_category(context.user_agent)).await;
+        }
+        if let (Some(key), Some(client_ip)) = (&self.visitor_key, context.client_ip) {
+            // Raw IPs are never stored; the keyed hash is stable per deployment so repeat visits match
+            let visitor = hmac::sign(key, client_ip.as_bytes());
+            if let Err(e) = self.repository.record_visitor(short_code, visitor.as_ref()).await {
+                error!(short_code = %short_code, error = %e, "Failed to record visitor");
             }
-            Ok(None) => Err(UrlServiceError::NotFound(short_code.to_string())),
-            Err(e) => Err(UrlServiceError::StorageError(e)),
         }
+
+        // Best effort and left to the periodic flush, so it never slows down or fails a redirect
+        if let Err(e) = self.repository.touch(short_code, now_millis()).await {
+            error!(short_code = %short_code, error = %e, "Failed to record last access");
+        }
+
+        Ok(ResolvedUrl::new(url, &record))
     }
-} 
\ No newline at end of file
+
+    /// Best effort, like the click counter: a failed analytics write never fails the redirect.
+    async fn record_breakdown(&self, short_code: &str, dimension: &str, bucket: &str) {
+        if let Err(e) = self.repository.increment_breakdown(short_code, dimension, bucket).await {
+            error!(short_code = %short_code, dimension, error = %e, "Failed to record click breakdown");
+        }
+    }
+
+    async fn load_breakdown(&self, short_code: &str, dimension: &str) -> Result<Option<BTreeMap<String, u64>>, UrlServiceError> {
+        if !self.analytics_enabled {
+            return Ok(None);
+        }
+        Ok(Some(self.repository.get_breakdown(short_code, dimension).await?.into_iter().collect()))
+    }
+
+    /// Marks a one-time link as used. Only the request whose swap succeeds gets the destination;
+    /// concurrent readers see the consumed value and are turned away.
+    async fn consume(&self, short_code: &str, raw: &str, record: &UrlRecord) -> Result<(), UrlServiceError> {
+        let consumed = UrlRecord { consumed: true, ..record.clone() }.encode()?;
+        if !self.repository.compare_and_swap(short_code, raw, &consumed).await? {
+            return Err(UrlServiceError::Expired(short_code.to_string()));
+        }
+        self.repository.schedule_expiry(short_code, now_millis()).await?;
+        self.repository.flush().await?;
+        Ok(())
+    }
+
+    /// Resolves a short code without counting a click or consuming one-time links.
+    pub async fn peek_url(&self, short_code: &str) -> Result<String, UrlServiceError> {
+        Ok(self.peek_link(short_code).await?.url)
+    }
+
+    /// Like `peek_url`, also returning the link properties a redirect needs.
+    pub async fn peek_link(&self, short_code: &str) -> Result<ResolvedUrl, UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        let (_, record) = self.load_active(short_code).await?;
+        Ok(ResolvedUrl::new(self.decrypt_destination(&record)?, &record))
+    }
+
+    /// Checks that a short code resolves without consuming one-time links.
+    pub async fn ensure_active(&self, short_code: &str) -> Result<(), UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        self.load_active(short_code).await.map(|_| ())
+    }
+
+    /// Checks the management token for edits and deletes. With API keys configured the
+    /// request has already been authenticated, and links created without a token (imports,
+    /// older entries) stay open as before.
+    fn authorize_manage(&self, short_code: &str, record: &UrlRecord, provided: Option<&str>) -> Result<(), UrlServiceError> {
+        if !self.config.security.api_keys.is_empty() {
+            return Ok(());
+        }
+        let Some(expected) = record.manage_token_hash.as_deref() else {
+            return Ok(());
+        };
+
+        let provided = provided.map(EncryptionService::token_hash).unwrap_or_default();
+        verify_slices_are_equal(expected.as_bytes(), provided.as_bytes())
+            .map_err(|_| UrlServiceError::InvalidManageToken(short_code.to_string()))
+    }
+
+    /// Points an existing short code at a new destination, keeping its expiry and click count.
+    pub async fn update_url(&self, short_code: &str, url: String, manage_token: Option<&str>) -> Result<ShortenedUrl, UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        let (raw, record) = self.load_active(short_code).await?;
+        self.authorize_manage(short_code, &record, manage_token)?;
+        let normalized_url = self.validate_url(&url)?;
+        self.check_public_host(&normalized_url).await?;
+        let updated = UrlRecord {
+            destination: self.encrypt_value(&normalized_url)?,
+            ..record
+        };
+        let value = updated.encode()?;
+
+        // Only indexable links have an index entry to move
+        let (old_hash, new_hash) = if updated.is_indexable() {
+            (
+                self.stored_fingerprint(short_code, &raw),
+                Some(self.encryption.fingerprint(&normalized_url)),
+            )
+        } else {
+            (None, None)
+        };
+        if !self.repository.replace(short_code, &value, old_hash.as_deref(), new_hash.as_deref()).await? {
+            return Err(UrlServiceError::NotFound(short_code.to_string()));
+        }
+        self.flush_write().await?;
+
+        Ok(ShortenedUrl {
+            short_code: short_code.to_string(),
+            original_url: normalized_url,
+            full_short_url: self.full_short_url(short_code),
+            expires_at: updated.expires_at,
+            created_at: updated.created_at,
+            one_time: updated.one_time,
+            created: false,
+            manage_token: None,
+        })
+    }
+
+    /// Tombstones a mapping so it can be restored within the undo window, after which the
+    /// cleanup sweep removes it. With no undo window it is removed right away.
+    pub async fn delete_url(&self, short_code: &str, manage_token: Option<&str>) -> Result<(), UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        let raw = self.repository
+            .get(short_code)
+            .await?
+            .ok_or_else(|| UrlServiceError::NotFound(short_code.to_string()))?;
+        let record = UrlRecord::decode(&raw)?;
+        if record.deleted_at.is_some() {
+            return Err(UrlServiceError::NotFound(short_code.to_string()));
+        }
+        self.authorize_manage(short_code, &record, manage_token)?;
+
+        // The index entry can only be located through the destination's fingerprint
+        let url_hash = self.stored_fingerprint(short_code, &raw);
+        if self.delete_undo_window_ms == 0 {
+            if !self.repository.remove(short_code, url_hash.as_deref()).await? {
+                return Err(UrlServiceError::NotFound(short_code.to_string()));
+            }
+        } else {
+            // The index entry goes now so deduplication never hands out a deleted code
+            let deleted_at = now_millis();
+            let tombstone = UrlRecord { deleted_at: Some(deleted_at), ..record }.encode()?;
+            if !self.repository.replace(short_code, &tombstone, url_hash.as_deref(), None).await? {
+                return Err(UrlServiceError::NotFound(short_code.to_string()));
+            }
+            self.repository
+                .schedule_expiry(short_code, deleted_at.saturating_add(self.delete_undo_window_ms))
+                .await?;
+        }
+
+        self.flush_write().await?;
+        Ok(())
+    }
+
+    /// Undoes a delete while the undo window is open. Click counts and other analytics are
+    /// kept, and permanent links rejoin deduplication if no other code took their URL meanwhile.
+    pub async fn restore_url(&self, short_code: &str, manage_token: Option<&str>) -> Result<(), UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        let raw = self.repository
+            .get(short_code)
+            .await?
+            .ok_or_else(|| UrlServiceError::NotFound(short_code.to_string()))?;
+        let record = UrlRecord::decode(&raw)?;
+        let Some(deleted_at) = record.deleted_at else {
+            return Err(UrlServiceError::NotDeleted(short_code.to_string()));
+        };
+        self.authorize_manage(short_code, &record, manage_token)?;
+        if deleted_at.saturating_add(self.delete_undo_window_ms) <= now_millis() {
+            return Err(UrlServiceError::Expired(short_code.to_string()));
+        }
+
+        let restored = UrlRecord { deleted_at: None, ..record };
+        let value = restored.encode()?;
+        // Loses to a concurrent sweep or restore rather than resurrecting a purged entry
+        if !self.repository.compare_and_swap(short_code, &raw, &value).await? {
+            return Err(UrlServiceError::NotFound(short_code.to_string()));
+        }
+        if restored.is_indexable() {
+            if let Some(url_hash) = self.stored_fingerprint(short_code, &value) {
+                self.repository.index_if_absent(&url_hash, short_code).await?;
+            }
+        }
+
+        self.flush_write().await?;
+        Ok(())
+    }
+
+    /// Lists live mappings page by page; expired, consumed, exhausted and deleted links are left
+    /// out of both the page and `total`. Only the forward mapping tree is scanned, so index and
+    /// analytics entries never appear in the results. Counting live links reads every record.
+    pub async fn list_urls(&self, limit: usize, offset: usize) -> Result<UrlPage, UrlServiceError> {
+        let limit = limit.min(MAX_PAGE_SIZE);
+        let now = now_millis();
+        let mut total = 0;
+        let mut urls = Vec::with_capacity(limit);
+
+        let mut entries = self.repository.stream_prefix("").await?;
+        while let Some(entry) = entries.next().await {
+            let entry = entry?;
+            let short_code = String::from_utf8_lossy(&entry.key).into_owned();
+            let record = UrlRecord::decode(&String::from_utf8_lossy(&entry.value))?;
+            if self.is_dead(&short_code, &record, now).await? {
+                continue;
+            }
+            if total >= offset && urls.len() < limit {
+                if let Some(summary) = self.summarize(entry).await? {
+                    urls.push(summary);
+                }
+            }
+            total += 1;
+        }
+
+        Ok(UrlPage { total, urls })
+    }
+
+    /// Lists live mappings carrying a tag, ordered by code, reading only the codes in the tag index.
+    /// Index entries left behind by deleted or re-created codes are dropped along the way.
+    pub async fn list_tagged_urls(&self, tag: &str, limit: usize, offset: usize) -> Result<UrlPage, UrlServiceError> {
+        let tag = Self::normalize_tag(tag)?;
+        let limit = limit.min(MAX_PAGE_SIZE);
+        let now = now_millis();
+        let mut entries = Vec::new();
+
+        for short_code in self.repository.tagged_urls(&tag).await? {
+            let Some(raw) = self.repository.get(&short_code).await? else {
+                self.repository.untag_url(&tag, &short_code).await?;
+                continue;
+            };
+            let record = UrlRecord::decode(&raw)?;
+            if !record.tags.contains(&tag) {
+                self.repository.untag_url(&tag, &short_code).await?;
+                continue;
+            }
+            if !self.is_dead(&short_code, &record, now).await? {
+                entries.push(Entry { key: short_code.into_bytes(), value: raw.into_bytes() });
+            }
+        }
+
+        let total = entries.len();
+        let mut urls = Vec::with_capacity(limit);
+        for entry in entries.into_iter().skip(offset).take(limit) {
+            if let Some(summary) = self.summarize(entry).await? {
+                urls.push(summary);
+            }
+        }
+
+        Ok(UrlPage { total, urls })
+    }
+
+    /// The most-clicked live links, highest first. Counters for expired or dead links are
+    /// skipped, so when some of the top candidates don't qualify a wider set is fetched.
+    pub async fn top_urls(&self, limit: usize) -> Result<Vec<UrlSummary>, UrlServiceError> {
+        let limit = limit.min(MAX_TOP_LIMIT);
+        let mut candidates = limit;
+        let now = now_millis();
+
+        loop {
+            let top = self.repository.top_clicks(candidates).await?;
+            let exhausted = top.len() < candidates;
+            let mut urls = Vec::with_capacity(limit);
+
+            for (short_code, _) in top {
+                let Some(raw) = self.repository.get(&short_code).await? else {
+                    continue;
+                };
+                if self.is_dead(&short_code, &UrlRecord::decode(&raw)?, now).await? {
+                    continue;
+                }
+                let entry = Entry {
+                    key: short_code.into_bytes(),
+                    value: raw.into_bytes(),
+                };
+                if let Some(summary) = self.summarize(entry).await? {
+                    urls.push(summary);
+                }
+                if urls.len() >= limit {
+                    break;
+                }
+            }
+
+            if urls.len() >= limit || exhausted {
+                return Ok(urls);
+            }
+            candidates *= 2;
+        }
+    }
+
+    /// Streams every stored mapping lazily; pair with `summarize` to decrypt entries one at a time.
+    pub async fn stream_urls(&self) -> Result<EntryStream, UrlServiceError> {
+        Ok(self.repository.stream_prefix("").await?)
+    }
+
+    /// Decrypts a raw entry into a summary, returning `None` for entries that can't be read
+    /// and for dead ones (expired, consumed, out of clicks or deleted) awaiting removal, so
+    /// an export never carries a burned link back into a restored database.
+    pub async fn summarize(&self, entry: Entry) -> Result<Option<UrlSummary>, UrlServiceError> {
+        let short_code = String::from_utf8_lossy(&entry.key).into_owned();
+        let record = UrlRecord::decode(&String::from_utf8_lossy(&entry.value))?;
+        if self.is_dead(&short_code, &record, now_millis()).await? {
+            return Ok(None);
+        }
+        let (original_url, description) = match self
+            .decrypt_destination(&record)
+            .and_then(|url| Ok((url, self.decrypt_description(&record)?)))
+        {
+            Ok(decrypted) => decrypted,
+            Err(e) => {
+                warn!(short_code = %short_code, error = %e, "Skipping unreadable entry");
+                return Ok(None);
+            }
+        };
+
+        Ok(Some(UrlSummary {
+            description,
+            clicks: self.repository.get_clicks(&short_code).await?,
+            last_accessed: self.repository.get_last_accessed(&short_code).await?,
+            unique_visitors: None,
+            referrers: None,
+            user_agents: None,
+            short_code,
+            original_url,
+            expires_at: record.expires_at,
+            created_at: record.created_at,
+            one_time: record.one_time,
+            max_clicks: record.max_clicks,
+            tags: record.tags,
+        }))
+    }
+
+    /// Bytes the store occupies on disk; always `0` for the memory backend.
+    pub async fn storage_size(&self) -> Result<u64, UrlServiceError> {
+        Ok(self.repository.size_on_disk().await?)
+    }
+
+    /// Rewrites live data so the store can give back space left by deletes and expiry
+    /// sweeps. Returns the on-disk size in bytes before and after.
+    pub async fn compact_storage(&self) -> Result<(u64, u64), UrlServiceError> {
+        let before = self.repository.size_on_disk().await?;
+        self.repository.compact().await?;
+        let after = self.repository.size_on_disk().await?;
+        Ok((before, after))
+    }
+
+    /// Number of stored links, including expired ones not yet purged.
+    pub async fn count_urls(&self) -> Result<usize, UrlServiceError> {
+        Ok(self.repository.count().await?)
+    }
+
+    /// Reads a mapping's metadata and click count. Expired links still report stats.
+    pub async fn get_stats(&self, short_code: &str) -> Result<UrlSummary, UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        let raw = self.repository
+            .get(short_code)
+            .await?
+            .ok_or_else(|| UrlServiceError::NotFound(short_code.to_string()))?;
+        let record = UrlRecord::decode(&raw)?;
+
+        Ok(UrlSummary {
+            short_code: short_code.to_string(),
+            original_url: self.decrypt_destination(&record)?,
+            description: self.decrypt_description(&record)?,
+            clicks: self.repository.get_clicks(short_code).await?,
+            expires_at: record.expires_at,
+            created_at: record.created_at,
+            last_accessed: self.repository.get_last_accessed(short_code).await?,
+            one_time: record.one_time,
+            max_clicks: record.max_clicks,
+            unique_visitors: match self.visitor_key {
+                Some(_) => Some(self.repository.count_visitors(short_code).await?),
+                None => None,
+            },
+            referrers: self.load_breakdown(short_code, REFERRER_DIMENSION).await?,
+            user_agents: self.load_breakdown(short_code, USER_AGENT_DIMENSION).await?,
+            tags: record.tags,
+        })
+    }
+
+    /// Clicks per UTC day for the last `days` days, oldest first and ending today. Days
+    /// without clicks are included with a zero count. `days` is capped at the retention period.
+    pub async fn daily_clicks(&self, short_code: &str, days: u32) -> Result<Vec<DailyClicks>, UrlServiceError> {
+        let short_code = &self.lookup_code(short_code).await?;
+        if self.repository.get(short_code).await?.is_none() {
+            return Err(UrlServiceError::NotFound(short_code.to_string()));
+        }
+
+        let days = days.clamp(1, self.config.analytics.daily_retention_days);
+        let today = day_number(now_millis());
+        let since = today.saturating_sub(days - 1);
+        let counts: HashMap<u32, u64> = self.repository
+            .get_daily_clicks(short_code, since)
+            .await?
+            .into_iter()
+            .collect();
+
+        Ok((since..=today)
+            .map(|day| DailyClicks {
+                date: format_day(day),
+                count: counts.get(&day).copied().unwrap_or(0),
+            })
+            .collect())
+    }
+
+    /// Drops per-day click buckets older than the retention period. Returns how many were removed.
+    pub async fn purge_daily_clicks(&self) -> Result<usize, UrlServiceError> {
+        let cutoff = day_number(now_millis()).saturating_sub(self.config.analytics.daily_retention_days - 1);
+        Ok(self.repository.purge_daily_clicks(cutoff).await?)
+    }
+
+    /// Removes mappings whose scheduled expiry has passed and that can no longer resolve.
+    /// Schedule entries are dropped either way, so stale ones (e.g. for deleted or
+    /// re-created codes) are cleaned up too. Returns how many mappings were purged.
+    pub async fn purge_expired(&self) -> Result<usize, UrlServiceError> {
+        let now = now_millis();
+        let mut purged = 0;
+
+        loop {
+            let due = self.repository.due_expirations(now, PURGE_BATCH_SIZE).await?;
+            let batch_len = due.len();
+
+            for (expires_at, short_code) in due {
+                if let Some(raw) = self.repository.get(&short_code).await? {
+                    let record = UrlRecord::decode(&raw)?;
+                    // Dead links are never indexable and tombstones drop their index entry
+                    // on delete, so there is no index entry to clean up
+                    if self.is_dead(&short_code, &record, now).await?
+                        && self.repository.remove(&short_code, None).await?
+                    {
+                        purged += 1;
+                    }
+                }
+                self.repository.unschedule_expiry(&short_code, expires_at).await?;
+            }
+
+            if batch_len < PURGE_BATCH_SIZE {
+                break;
+            }
+        }
+
+        if purged > 0 {
+            self.repository.flush().await?;
+        }
+        Ok(purged)
+    }
+
+    /// Reports whether a custom alias could be claimed right now. Malformed or reserved
+    /// aliases are an error rather than `false` so callers can tell the two apart.
+    pub async fn is_alias_available(&self, alias: &str) -> Result<bool, UrlServiceError> {
+        let alias = self.normalize_alias(alias);
+        self.validate_alias(&alias)?;
+        Ok(self.repository.get(&alias).await?.is_none())
+    }
+
+    /// Performs a trivial read so health checks fail when storage does.
+    pub async fn check_storage(&self) -> Result<(), UrlServiceError> {
+        self.repository.get(HEALTH_CHECK_KEY).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CodeStrategy;
+    use crate::repositories::memory_repository::InMemoryUrlRepository;
+    use crate::services::test_support::{service_with, test_config, test_service};
+
+    async fn shorten(service: &UrlService, url: &str, options: ShortenOptions) -> ShortenedUrl {
+        service.shorten_url(url.to_string(), options, None).await.expect("shorten")
+    }
+
+    #[actix_web::test]
+    async fn shorten_resolve_delete_round_trip() {
+        let service = test_service();
+        let shortened = shorten(&service, "https://example.com/page", ShortenOptions::default()).await;
+        assert!(shortened.created);
+        assert_eq!(service.peek_url(&shortened.short_code).await.unwrap(), "https://example.com/page");
+
+        let again = shorten(&service, "https://example.com/page", ShortenOptions::default()).await;
+        assert_eq!(again.short_code, shortened.short_code);
+
+        service.delete_url(&shortened.short_code, shortened.manage_token.as_deref()).await.unwrap();
+        assert!(matches!(service.peek_url(&shortened.short_code).await, Err(UrlServiceError::Deleted(_))));
+        assert!(matches!(service.peek_url("missing").await, Err(UrlServiceError::NotFound(_))));
+    }
+
+    #[actix_web::test]
+    async fn list_total_counts_only_live_links() {
+        let service = test_service();
+        shorten(&service, "https://example.com/live", ShortenOptions::default()).await;
+        let deleted = shorten(&service, "https://example.com/deleted", ShortenOptions::default()).await;
+        let exhausted = shorten(
+            &service,
+            "https://example.com/exhausted",
+            ShortenOptions { max_clicks: Some(1), ..Default::default() },
+        )
+        .await;
+
+        service.delete_url(&deleted.short_code, deleted.manage_token.as_deref()).await.unwrap();
+        service.resolve_click(&exhausted.short_code, &ClickContext::default()).await.unwrap();
+
+        let page = service.list_urls(10, 0).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.urls.len(), 1);
+        assert_eq!(page.urls[0].original_url, "https://example.com/live");
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_alias_claims_have_a_single_winner() {
+        let service = Arc::new(test_service());
+        let requests: Vec<_> = ["https://example.com/first", "https://example.com/second"]
+            .into_iter()
+            .map(|url| {
+                let service = Arc::clone(&service);
+                tokio::spawn(async move {
+                    let options = ShortenOptions { custom_alias: Some("launch".to_string()), ..Default::default() };
+                    service.shorten_url(url.to_string(), options, None).await
+                })
+            })
+            .collect();
+
+        let mut created = 0;
+        let mut conflicts = 0;
+        for request in requests {
+            match request.await.unwrap() {
+                Ok(shortened) => {
+                    assert!(shortened.created);
+                    created += 1;
+                }
+                Err(UrlServiceError::AliasExists(_)) => conflicts += 1,
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        assert_eq!((created, conflicts), (1, 1));
+    }
+
+    #[actix_web::test]
+    async fn only_allowed_schemes_are_accepted() {
+        let service = test_service();
+        for url in ["javascript:alert(1)", "data:text/html,<script>alert(1)</script>", "ftp://example.com/file", "JavaScript:alert(1)"] {
+            let result = service.shorten_url(url.to_string(), ShortenOptions::default(), None).await;
+            assert!(matches!(result, Err(UrlServiceError::InvalidUrl(_))), "{} was accepted", url);
+        }
+        for url in ["http://example.com/", "https://example.com/", "HTTPS://example.com/upper"] {
+            shorten(&service, url, ShortenOptions::default()).await;
+        }
+    }
+
+    #[actix_web::test]
+    async fn short_urls_on_ipv6_hosts_parse() {
+        let mut config = test_config();
+        config.server.host = "::1".parse().unwrap();
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+
+        let shortened = shorten(&service, "https://example.com/", ShortenOptions::default()).await;
+        let url = Url::parse(&shortened.full_short_url).unwrap();
+        assert_eq!(url.host(), Some(url::Host::Ipv6("::1".parse().unwrap())));
+        assert_eq!(url.path(), format!("/{}", shortened.short_code));
+    }
+
+    #[actix_web::test]
+    async fn equivalent_urls_collapse_to_one_code() {
+        let service = test_service();
+        let first = shorten(&service, "http://EXAMPLE.com:80/a", ShortenOptions::default()).await;
+        let second = shorten(&service, "http://example.com/a#x", ShortenOptions::default()).await;
+
+        assert_eq!(first.original_url, "http://example.com/a");
+        assert_eq!(second.short_code, first.short_code);
+        assert!(!second.created);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn one_time_links_resolve_exactly_once_under_concurrency() {
+        for max_clicks in [None, Some(3)] {
+            let service = Arc::new(test_service());
+            let options = ShortenOptions { one_time: true, max_clicks, ..Default::default() };
+            let shortened = shorten(&service, "https://example.com/secret", options).await;
+            assert_eq!(resolve_concurrently(&service, &shortened.short_code, 16).await, 1);
+
+            // Requests that lost the race are not counted as clicks
+            let stats = service.get_stats(&shortened.short_code).await.unwrap();
+            assert_eq!(stats.clicks, 1, "max_clicks {:?}", max_clicks);
+        }
+    }
+
+    /// Resolves a code from `requests` tasks at once, returning how many got the destination.
+    async fn resolve_concurrently(service: &Arc<UrlService>, short_code: &str, requests: usize) -> usize {
+
+        let hits: Vec<_> = (0..requests)
+            .map(|_| {
+                let service = Arc::clone(service);
+                let short_code = short_code.to_string();
+                tokio::spawn(async move { service.resolve_click(&short_code, &ClickContext::default()).await })
+            })
+            .collect();
+        let mut resolved = 0;
+        for hit in hits {
+            match hit.await.unwrap() {
+                Ok(link) => {
+                    assert_eq!(link.url, "https://example.com/secret");
+                    resolved += 1;
+                }
+                Err(UrlServiceError::Expired(_)) => {}
+                Err(e) => panic!("unexpected error: {}", e),
+            }
+        }
+        resolved
+    }
+
+    async fn export(service: &UrlService) -> Vec<UrlSummary> {
+        let mut entries = service.stream_urls().await.unwrap();
+        let mut summaries = Vec::new();
+        while let Some(entry) = entries.next().await {
+            if let Some(summary) = service.summarize(entry.unwrap()).await.unwrap() {
+                summaries.push(summary);
+            }
+        }
+        summaries
+    }
+
+    #[actix_web::test]
+    async fn backups_keep_link_limits_and_drop_dead_links() {
+        let service = test_service();
+        let consumed = shorten(&service, "https://example.com/consumed", ShortenOptions { one_time: true, ..Default::default() }).await;
+        let one_time = shorten(&service, "https://example.com/once", ShortenOptions { one_time: true, ..Default::default() }).await;
+        let limited = shorten(&service, "https://example.com/limited", ShortenOptions { max_clicks: Some(2), ..Default::default() }).await;
+        service.resolve_click(&consumed.short_code, &ClickContext::default()).await.unwrap();
+        service.resolve_click(&limited.short_code, &ClickContext::default()).await.unwrap();
+
+        let backup = export(&service).await;
+        assert_eq!(backup.len(), 2);
+        assert!(backup.iter().all(|summary| summary.short_code != consumed.short_code));
+
+        let restored = test_service();
+        let entries = backup
+            .into_iter()
+            .map(|summary| ImportEntry {
+                short_code: summary.short_code,
+                original_url: summary.original_url,
+                expires_at: summary.expires_at,
+                created_at: summary.created_at,
+                one_time: summary.one_time,
+                max_clicks: summary.max_clicks,
+                clicks: summary.clicks,
+                description: summary.description,
+                tags: summary.tags,
+            })
+            .collect();
+        for result in restored.import_urls(entries, false).await.unwrap() {
+            assert_eq!(result.unwrap(), ImportOutcome::Created);
+        }
+
+        restored.resolve_click(&one_time.short_code, &ClickContext::default()).await.unwrap();
+        assert!(restored.resolve_click(&one_time.short_code, &ClickContext::default()).await.is_err());
+        restored.resolve_click(&limited.short_code, &ClickContext::default()).await.unwrap();
+        assert!(restored.resolve_click(&limited.short_code, &ClickContext::default()).await.is_err());
+    }
+
+    #[actix_web::test]
+    async fn dead_aliases_are_not_handed_back() {
+        let service = test_service();
+        let alias = |name: &str| ShortenOptions { custom_alias: Some(name.to_string()), ..Default::default() };
+        let deleted = shorten(&service, "https://example.com/a", alias("deleted")).await;
+        service.delete_url(&deleted.short_code, deleted.manage_token.as_deref()).await.unwrap();
+        let exhausted = shorten(
+            &service,
+            "https://example.com/b",
+            ShortenOptions { max_clicks: Some(1), ..alias("exhausted") },
+        )
+        .await;
+        service.resolve_click(&exhausted.short_code, &ClickContext::default()).await.unwrap();
+        let live = shorten(&service, "https://example.com/c", alias("live")).await;
+
+        for (url, name) in [("https://example.com/a", "deleted"), ("https://example.com/b", "exhausted")] {
+            let result = service.shorten_url(url.to_string(), alias(name), None).await;
+            assert!(matches!(result, Err(UrlServiceError::AliasExists(_))), "{} was handed back", name);
+        }
+        let again = shorten(&service, "https://example.com/c", alias("live")).await;
+        assert_eq!(again.short_code, live.short_code);
+        assert!(!again.created);
+    }
+
+    #[actix_web::test]
+    async fn self_referential_destinations_are_rejected() {
+        let mut config = test_config();
+        config.server.public_base_url = Some("https://sho.rt".to_string());
+        config.server.vanity_domains = vec!["go.example.org".to_string()];
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+        let existing = shorten(&service, "https://example.com/", ShortenOptions::default()).await;
+
+        for url in [
+            "https://sho.rt/".to_string(),
+            "http://SHO.RT/anything".to_string(),
+            existing.full_short_url.clone(),
+            format!("https://go.example.org/{}", existing.short_code),
+        ] {
+            let result = service.shorten_url(url.clone(), ShortenOptions::default(), None).await;
+            assert!(matches!(result, Err(UrlServiceError::SelfReference(_))), "{} was accepted", url);
+        }
+        shorten(&service, "https://sho.rt.example.com/", ShortenOptions::default()).await;
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_redirects_count_every_click() {
+        const REDIRECTS: u64 = 200;
+        let service = Arc::new(test_service());
+        let shortened = shorten(&service, "https://example.com/popular", ShortenOptions::default()).await;
+
+        let redirects: Vec<_> = (0..REDIRECTS)
+            .map(|_| {
+                let service = Arc::clone(&service);
+                let short_code = shortened.short_code.clone();
+                tokio::spawn(async move { service.resolve_click(&short_code, &ClickContext::default()).await })
+            })
+            .collect();
+        for redirect in redirects {
+            redirect.await.unwrap().unwrap();
+        }
+
+        assert_eq!(service.get_stats(&shortened.short_code).await.unwrap().clicks, REDIRECTS);
+    }
+
+    #[actix_web::test]
+    async fn private_hosts_are_rejected_and_public_ones_pinned() {
+        let mut config = test_config();
+        config.shortener.block_private_hosts = true;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+
+        for url in ["http://10.1.2.3/", "http://127.0.0.1:8080/", "http://[::1]/", "http://100.64.0.1/", "http://[::ffff:192.168.0.1]/"] {
+            let result = service.check_public_host(url).await;
+            assert!(matches!(result, Err(UrlServiceError::DomainNotAllowed(_))), "{} was allowed", url);
+        }
+        assert_eq!(
+            service.check_public_host("https://93.184.216.34/").await.unwrap(),
+            vec!["93.184.216.34:443".parse::<SocketAddr>().unwrap()]
+        );
+    }
+
+    /// Answers each request on its own connection and records the requested paths.
+    fn redirecting_server(redirects: fn(&str, u16) -> String) -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let paths = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&paths);
+        std::thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request_line.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let response = format!(
+                    "HTTP/1.1 302 Found\r\nLocation: {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    redirects(&path, addr.port())
+                );
+                seen.lock().unwrap().push(path);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (addr, paths)
+    }
+
+    #[actix_web::test]
+    async fn probes_stay_on_checked_addresses_and_skip_internal_redirects() {
+        let (addr, paths) = redirecting_server(|path, port| match path {
+            "/start" => format!("http://probe.test:{}/next", port),
+            _ => "http://10.0.0.1/admin".to_string(),
+        });
+        let service = test_service();
+
+        // `probe.test` never resolves; the probe can only reach the server through the pin
+        let url = format!("http://probe.test:{}/start", addr.port());
+        service.verify_reachable(&url, &[addr]).await.unwrap();
+        assert_eq!(*paths.lock().unwrap(), vec!["/start", "/next"]);
+    }
+
+    #[actix_web::test]
+    async fn rejected_aliases_are_not_probed() {
+        let (addr, paths) = redirecting_server(|_, _| "/".to_string());
+        let service = test_service();
+        shorten(&service, "https://example.com/taken", ShortenOptions {
+            custom_alias: Some("taken".to_string()),
+            ..Default::default()
+        })
+        .await;
+
+        let url = format!("http://{}/probe", addr);
+        let probed = |alias: &str| ShortenOptions {
+            custom_alias: Some(alias.to_string()),
+            verify_reachable: true,
+            ..Default::default()
+        };
+        let taken = service.shorten_url(url.clone(), probed("taken"), None).await;
+        assert!(matches!(taken, Err(UrlServiceError::AliasExists(_))));
+        let invalid = service.shorten_url(url, probed("no spaces"), None).await;
+        assert!(matches!(invalid, Err(UrlServiceError::InvalidAlias(_))));
+        assert!(paths.lock().unwrap().is_empty());
+    }
+
+    #[actix_web::test]
+    async fn aliases_are_checked_before_the_host() {
+        let mut config = test_config();
+        config.shortener.block_private_hosts = true;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+        shorten(&service, "https://93.184.216.34/", ShortenOptions {
+            custom_alias: Some("taken".to_string()),
+            ..Default::default()
+        })
+        .await;
+
+        let options = ShortenOptions { custom_alias: Some("taken".to_string()), ..Default::default() };
+        let result = service.shorten_url("http://10.1.2.3/".to_string(), options, None).await;
+        assert!(matches!(result, Err(UrlServiceError::AliasExists(_))));
+    }
+
+    /// A one-letter, one-character code space, so every generated code is `a`.
+    fn single_code_config() -> Config {
+        let mut config = test_config();
+        config.shortener.code_length = 1;
+        config.shortener.code_alphabet = "a".to_string();
+        config
+    }
+
+    #[actix_web::test]
+    async fn generation_gives_up_after_bounded_collisions() {
+        let repository = Arc::new(InMemoryUrlRepository::new());
+        repository.store_if_absent("a", "taken", None).await.unwrap();
+        let service = service_with(repository, single_code_config());
+
+        let result = service.shorten_url("https://example.com".to_string(), ShortenOptions::default(), None).await;
+        assert!(matches!(result, Err(UrlServiceError::CodeGenerationFailed(MAX_CODE_ATTEMPTS))));
+    }
+
+    #[actix_web::test]
+    async fn generation_gives_up_when_every_code_is_reserved() {
+        let mut config = single_code_config();
+        config.shortener.reserved_codes = vec!["a".to_string()];
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+
+        let result = service.shorten_url("https://example.com".to_string(), ShortenOptions::default(), None).await;
+        assert!(matches!(result, Err(UrlServiceError::CodeGenerationFailed(MAX_CODE_ATTEMPTS))));
+    }
+
+    #[actix_web::test]
+    async fn generated_codes_skip_taken_custom_aliases() {
+        let mut config = test_config();
+        config.shortener.code_strategy = CodeStrategy::Sequential;
+        // Encodes as `A000`, then `A001`
+        config.shortener.code_sequence_start = 2_383_280;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+
+        let options = ShortenOptions { custom_alias: Some("A000".to_string()), ..Default::default() };
+        shorten(&service, "https://example.com/alias", options).await;
+
+        let generated = shorten(&service, "https://example.com/generated", ShortenOptions::default()).await;
+        assert_eq!(generated.short_code, "A001");
+        assert_eq!(service.peek_url("A000").await.unwrap(), "https://example.com/alias");
+    }
+
+    #[actix_web::test]
+    async fn custom_aliases_count_against_the_generated_code_space() {
+        let mut config = single_code_config();
+        config.shortener.code_length = 3;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+
+        let options = ShortenOptions { custom_alias: Some("aaa".to_string()), ..Default::default() };
+        shorten(&service, "https://example.com/alias", options).await;
+
+        let result = service.shorten_url("https://example.com/generated".to_string(), ShortenOptions::default(), None).await;
+        assert!(matches!(result, Err(UrlServiceError::CodeGenerationFailed(MAX_CODE_ATTEMPTS))));
+        assert_eq!(service.peek_url("aaa").await.unwrap(), "https://example.com/alias");
+    }
+
+    async fn alias_error(service: &UrlService, alias: &str) -> String {
+        match service.is_alias_available(alias).await {
+            Err(UrlServiceError::InvalidAlias(message)) => message,
+            other => panic!("expected InvalidAlias for {:?}, got {:?}", alias, other.map_err(|e| e.to_string())),
+        }
+    }
+
+    fn import_entry(short_code: &str) -> ImportEntry {
+        ImportEntry {
+            short_code: short_code.to_string(),
+            original_url: format!("https://example.com/{}", short_code.len()),
+            expires_at: None,
+            created_at: None,
+            one_time: false,
+            max_clicks: None,
+            clicks: 0,
+            description: None,
+            tags: Vec::new(),
+        }
+    }
+
+    #[actix_web::test]
+    async fn imports_accept_generated_codes_that_aliases_may_not_use() {
+        let mut config = test_config();
+        config.shortener.alias_min_length = 6;
+        config.shortener.allow_numeric_aliases = false;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+        assert!(service.is_alias_available("42").await.is_err());
+
+        let results = service.import_urls(vec![import_entry("42"), import_entry("A1")], false).await.unwrap();
+        assert!(results.iter().all(|result| matches!(result, Ok(ImportOutcome::Created))));
+        assert_eq!(service.peek_url("42").await.unwrap(), "https://example.com/2");
+    }
+
+    #[actix_web::test]
+    async fn imports_still_reject_unsafe_codes() {
+        let service = test_service();
+        let entries = vec![import_entry(""), import_entry("a/b"), import_entry("api")];
+        for result in service.import_urls(entries, false).await.unwrap() {
+            assert!(matches!(result, Err(UrlServiceError::InvalidAlias(_))));
+        }
+    }
+
+    #[actix_web::test]
+    async fn alias_length_bounds_come_from_config() {
+        let mut config = test_config();
+        config.shortener.alias_min_length = 4;
+        config.shortener.alias_max_length = 6;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+
+        assert!(alias_error(&service, "abc").await.contains("at least 4 characters"));
+        assert!(service.is_alias_available("abcd").await.unwrap());
+        assert!(service.is_alias_available("abcdef").await.unwrap());
+        assert!(alias_error(&service, "abcdefg").await.contains("not exceed 6 characters"));
+    }
+
+    #[actix_web::test]
+    async fn default_alias_length_bounds() {
+        let service = test_service();
+        assert!(alias_error(&service, "ab").await.contains("at least 3 characters"));
+        assert!(service.is_alias_available("abc").await.unwrap());
+        assert!(service.is_alias_available(&"a".repeat(32)).await.unwrap());
+        assert!(alias_error(&service, &"a".repeat(33)).await.contains("not exceed 32 characters"));
+    }
+
+    #[actix_web::test]
+    async fn blank_aliases_are_rejected_before_other_checks() {
+        let service = test_service();
+        assert!(alias_error(&service, "").await.contains("empty or only whitespace"));
+        assert!(alias_error(&service, "   ").await.contains("empty or only whitespace"));
+
+        let options = ShortenOptions { custom_alias: Some("   ".to_string()), ..Default::default() };
+        let result = service.shorten_url("https://example.com".to_string(), options, None).await;
+        assert!(matches!(result, Err(UrlServiceError::InvalidAlias(_))));
+    }
+
+    #[actix_web::test]
+    async fn aliases_are_trimmed_before_validation() {
+        let service = test_service();
+        assert!(alias_error(&service, "  ab  ").await.contains("at least 3 characters"));
+        assert!(service.is_alias_available("  abc  ").await.unwrap());
+
+        let options = ShortenOptions { custom_alias: Some("  abc  ".to_string()), ..Default::default() };
+        assert_eq!(shorten(&service, "https://example.com", options).await.short_code, "abc");
+    }
+
+    #[actix_web::test]
+    async fn numeric_aliases_follow_config() {
+        assert!(test_service().is_alias_available("2024").await.unwrap());
+
+        let mut config = test_config();
+        config.shortener.allow_numeric_aliases = false;
+        let service = service_with(Arc::new(InMemoryUrlRepository::new()), config);
+        assert!(alias_error(&service, "2024").await.contains("only of digits"));
+        assert!(service.is_alias_available("2024a").await.unwrap());
+    }
+}